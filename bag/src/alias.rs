@@ -0,0 +1,291 @@
+//! Glob-based path aliasing. The same rules are used by `bagger` to resolve
+//! `[alias]` at build time and by bags to resolve `[runtime.alias]` at run
+//! time.
+//!
+//! Patterns support `?` (one character within a path segment), `*` (any
+//! characters within a path segment), and `**` (any characters across path
+//! segments). The text matched by each wildcard in the source pattern is
+//! substituted, in order, for the wildcards in the target pattern.
+//!
+//! Bags which load files at run time hold a `RuntimePath`: the logical path
+//! from the source code, plus the path `bagger` resolved through
+//! `[runtime.alias]` at build time. The build-time path is used unless the
+//! program installs its own table with `set_runtime_aliases`, and relative
+//! paths can be rooted with `set_runtime_root` (for example, next to the
+//! executable).
+
+use ::fail;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::env;
+use std::io;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Lit(String),
+    One,
+    Any,
+    Deep,
+}
+
+impl Part {
+    fn is_wild(&self) -> bool {
+        !matches!(*self, Part::Lit(_))
+    }
+}
+
+/// A parsed glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    source: String,
+    parts: Vec<Part>,
+}
+
+impl FromStr for Glob {
+    type Err = fail::Error;
+
+    fn from_str(source: &str) -> Result<Glob, fail::Error> {
+        let mut parts = Vec::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            let part = match c {
+                '?' => Part::One,
+                '*' => if chars.peek() == Some(&'*') {
+                    chars.next();
+                    Part::Deep
+                } else {
+                    Part::Any
+                },
+                c => {
+                    if let Some(&mut Part::Lit(ref mut lit)) = parts.last_mut() {
+                        lit.push(c);
+                        continue
+                    }
+                    Part::Lit(c.to_string())
+                },
+            };
+
+            match (parts.last(), &part) {
+                (Some(&Part::Any), &Part::Any) |
+                (Some(&Part::Any), &Part::Deep) |
+                (Some(&Part::Deep), &Part::Any) |
+                (Some(&Part::Deep), &Part::Deep) =>
+                    bail!("glob \"{}\" has ambiguous adjacent wildcards", source),
+                _ => (),
+            }
+            parts.push(part);
+        }
+
+        Ok(Glob { source: source.to_owned(), parts })
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn match_parts(parts: &[Part], text: &str, caps: &mut Vec<String>) -> bool {
+    let (part, rest) = match parts.split_first() {
+        Some(x) => x,
+        None => return text.is_empty(),
+    };
+
+    match *part {
+        Part::Lit(ref lit) => text.starts_with(lit.as_str())
+            && match_parts(rest, &text[lit.len()..], caps),
+        Part::One => match text.chars().next() {
+            Some(c) if c != '/' => {
+                caps.push(c.to_string());
+                if match_parts(rest, &text[c.len_utf8()..], caps) { return true }
+                caps.pop();
+                false
+            },
+            _ => false,
+        },
+        Part::Any | Part::Deep => {
+            let deep = *part == Part::Deep;
+            let ends = text.char_indices()
+                .map(|(i, _)| i)
+                .chain(Some(text.len()));
+            for end in ends {
+                if !deep && text[..end].contains('/') { break }
+                caps.push(text[..end].to_owned());
+                if match_parts(rest, &text[end..], caps) { return true }
+                caps.pop();
+            }
+            false
+        },
+    }
+}
+
+impl Glob {
+    /// Match a path against this pattern, returning the text matched by each
+    /// wildcard.
+    pub fn captures(&self, path: &str) -> Option<Vec<String>> {
+        let mut caps = Vec::new();
+        if match_parts(&self.parts, path, &mut caps) {
+            Some(caps)
+        } else {
+            None
+        }
+    }
+
+    /// Does the path match this pattern?
+    pub fn matches(&self, path: &str) -> bool {
+        self.captures(path).is_some()
+    }
+
+    /// Number of wildcards in this pattern.
+    pub fn wildcards(&self) -> usize {
+        self.parts.iter().filter(|p| p.is_wild()).count()
+    }
+
+    /// Substitute captured text for the wildcards in this pattern.
+    pub fn fill(&self, caps: &[String]) -> String {
+        let mut caps = caps.iter();
+        let mut out = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Lit(ref lit) => out.push_str(lit),
+                _ => if let Some(cap) = caps.next() { out.push_str(cap) },
+            }
+        }
+        out
+    }
+
+    /// How specific this pattern is. Patterns with more literal characters
+    /// are more specific, and ties go to the pattern with fewer `**`.
+    pub fn specificity(&self) -> (usize, isize) {
+        let mut lits = 0;
+        let mut deeps = 0;
+        for part in &self.parts {
+            match *part {
+                Part::Lit(ref lit) => lits += lit.chars().count(),
+                Part::Deep => deeps += 1,
+                _ => (),
+            }
+        }
+        (lits, -deeps)
+    }
+}
+
+/// A single alias rule that matched some path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alias<'a> {
+    pub from: &'a Glob,
+    pub to: &'a Glob,
+    pub path: String,
+}
+
+/// A set of alias rules, from glob pattern to glob pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AliasTable {
+    rules: Vec<(Glob, Glob)>,
+}
+
+impl AliasTable {
+    pub fn new() -> AliasTable {
+        AliasTable { rules: Vec::new() }
+    }
+
+    /// Add a rule. Both patterns must have the same number of wildcards.
+    pub fn insert(&mut self, from: &str, to: &str) -> Result<(), fail::Error> {
+        let from = Glob::from_str(from)?;
+        let to = Glob::from_str(to)?;
+        if from.wildcards() != to.wildcards() {
+            bail!("alias \"{}\" = \"{}\" has mismatched wildcards", from, to)
+        }
+        self.rules.push((from, to));
+        Ok(())
+    }
+
+    /// Find the most specific rule matching a path. If several rules are
+    /// equally specific, the first inserted wins.
+    pub fn lookup(&self, path: &str) -> Option<Alias<'_>> {
+        let mut best: Option<(Alias, (usize, isize))> = None;
+        for (from, to) in &self.rules {
+            let caps = match from.captures(path) {
+                Some(c) => c,
+                None => continue,
+            };
+            let spec = from.specificity();
+            if best.as_ref().map(|&(_, s)| spec > s).unwrap_or(true) {
+                best = Some((Alias { from, to, path: to.fill(&caps) }, spec));
+            }
+        }
+        best.map(|(a, _)| a)
+    }
+
+    /// Resolve a path through the most specific matching rule.
+    pub fn resolve(&self, path: &str) -> Option<String> {
+        self.lookup(path).map(|a| a.path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+struct Runtime {
+    aliases: AliasTable,
+    root: Option<PathBuf>,
+}
+
+lazy_static! {
+    static ref RUNTIME: RwLock<Runtime> = RwLock::new(Runtime {
+        aliases: AliasTable::new(),
+        root: None,
+    });
+}
+
+/// Override the aliases baked in at build time. Logical paths matching this
+/// table are resolved through it instead.
+pub fn set_runtime_aliases(table: AliasTable) {
+    RUNTIME.write().unwrap().aliases = table;
+}
+
+/// Resolve relative run-time paths against the given directory instead of
+/// the working directory.
+pub fn set_runtime_root<P: Into<PathBuf>>(root: P) {
+    RUNTIME.write().unwrap().root = Some(root.into());
+}
+
+/// Resolve relative run-time paths against the directory containing the
+/// current executable.
+pub fn use_exe_root() -> io::Result<()> {
+    let exe = env::current_exe()?;
+    let dir = exe.parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "executable has no parent"))?;
+    set_runtime_root(dir);
+    Ok(())
+}
+
+/// The path of an asset loaded at run time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RuntimePath {
+    /// Path as written in the source code.
+    pub logical: &'static str,
+    /// Path resolved by `bagger` at build time.
+    pub baked: &'static str,
+}
+
+impl RuntimePath {
+    pub fn new(logical: &'static str, baked: &'static str) -> RuntimePath {
+        RuntimePath { logical, baked }
+    }
+
+    /// Find the file to load, using the run-time alias table and root.
+    pub fn resolve(&self) -> PathBuf {
+        let runtime = RUNTIME.read().unwrap();
+        let path = PathBuf::from(runtime.aliases.resolve(self.logical)
+            .unwrap_or_else(|| self.baked.to_owned()));
+        match runtime.root {
+            Some(ref root) if path.is_relative() => root.join(path),
+            _ => path,
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use ::{AsyncBag, Load, fail};
+use super::{panic_error, clone_input, CloneInput};
+use super::map::PanicPolicy;
+use std::borrow::Borrow;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::mpsc::{self, Sender};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+type Job = Box<FnOnce() + Send>;
+
+/// Start the threads that apply the functions of every async map, and return
+/// the queue they take work from. There is one thread per core, so a load
+/// that waits on another async map can hold up the rest.
+fn start_workers() -> Mutex<Sender<Job>> {
+    let (send, recv) = mpsc::channel::<Job>();
+    let recv = Arc::new(Mutex::new(recv));
+    let count = thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+    for i in 0..count {
+        let recv = recv.clone();
+        thread::Builder::new()
+            .name(format!("bag-worker-{}", i))
+            .spawn(move || loop {
+                let job = match recv.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                job()
+            })
+            .expect("could not start bag worker");
+    }
+    Mutex::new(send)
+}
+
+lazy_static! {
+    static ref WORKERS: Mutex<Sender<Job>> = start_workers();
+}
+
+/// The result of a map, shared with the thread that applies it. A panic in the
+/// function is kept as an error.
+struct Shared<B> {
+    data: OnceLock<Result<B, fail::Error>>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl<B> Shared<B> {
+    fn finish(&self, data: Result<B, fail::Error>) {
+        let _ = self.data.set(data);
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Applies a function on a worker thread the first time it is polled, so that
+/// slow work such as reading files does not block an executor. Every async map
+/// shares the same few workers.
+struct AsyncState<A, B, F> {
+    shared: Arc<Shared<B>>,
+    start: Mutex<Option<(A, F)>>,
+    /// Copies the input for attempts that may be retried.
+    retry: Option<CloneInput<A, F>>,
+    /// Most panics to retry.
+    retries: usize,
+}
+
+impl<A, B, F> AsyncState<A, B, F>
+    where A: Send + 'static, B: Send + Sync + 'static, F: FnOnce(A) -> B + Send + 'static
+{
+    fn new(data: A, func: F) -> Self {
+        AsyncState {
+            shared: Arc::new(Shared {
+                data: OnceLock::new(),
+                wakers: Mutex::new(Vec::new()),
+            }),
+            start: Mutex::new(Some((data, func))),
+            retry: None,
+            retries: 0,
+        }
+    }
+
+    fn with_policy(data: A, func: F, policy: PanicPolicy) -> Self
+        where A: Clone, F: Clone
+    {
+        let mut state = AsyncState::new(data, func);
+        if let PanicPolicy::Retry(times) = policy {
+            state.retries = times;
+            state.retry = Some(clone_input::<A, F>);
+        }
+        state
+    }
+
+    fn poll(&self, cx: &mut Context) -> Poll<Result<&B, &fail::Error>> {
+        {
+            // the loading thread sets the data before taking the wakers
+            let mut wakers = self.shared.wakers.lock().unwrap();
+            if let Some(data) = self.shared.data.get() {
+                return Poll::Ready(data.as_ref())
+            }
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+
+        if let Some(input) = self.start.lock().unwrap().take() {
+            let shared = self.shared.clone();
+            let (retry, retries) = (self.retry, self.retries);
+            let job: Job = Box::new(move || {
+                let mut input = Some(input);
+                let mut panics = 0;
+                let data = loop {
+                    let (data, func) = match retry {
+                        Some(copy) if panics < retries => copy(input.as_ref().unwrap()),
+                        _ => input.take().unwrap(),
+                    };
+                    match panic::catch_unwind(AssertUnwindSafe(move || func(data))) {
+                        Ok(data) => break Ok(data),
+                        Err(payload) => {
+                            if input.is_none() { break Err(panic_error(payload)) }
+                            panics += 1;
+                        },
+                    }
+                };
+                shared.finish(data)
+            });
+            WORKERS.lock().unwrap().send(job).expect("bag workers stopped");
+        }
+        Poll::Pending
+    }
+}
+
+/// Future returned by the `AsyncBag` implementations of the async maps.
+struct Loading<'a, S: 'a, T: ?Sized + 'a> {
+    state: &'a S,
+    poll: fn(&'a S, &mut Context) -> Poll<Result<&'a T, &'a fail::Error>>,
+    _ph: PhantomData<&'a T>,
+}
+
+impl<'a, S: 'a, T: ?Sized + 'a> Future for Loading<'a, S, T> {
+    type Output = Result<&'a T, &'a fail::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        (self.poll)(self.state, cx)
+    }
+}
+
+/// Like `LazyMap`, but the function is applied on a shared worker thread, and
+/// the data is awaited through `AsyncBag`.
+pub struct AsyncLazyMap<A, B, F: FnOnce(A) -> B> {
+    state: AsyncState<A, B, F>,
+}
+impl<A, B, F> AsyncLazyMap<A, B, F>
+    where A: Send + 'static, B: Send + Sync + 'static, F: FnOnce(A) -> B + Send + 'static
+{
+    pub fn new(data: A, func: F) -> Self {
+        AsyncLazyMap { state: AsyncState::new(data, func) }
+    }
+
+    /// Create a map that acts on panics by the given policy. Since nobody is
+    /// waiting on a borrow in between, retries run right away, on the same
+    /// thread.
+    pub fn with_policy(data: A, func: F, policy: PanicPolicy) -> Self
+        where A: Clone, F: Clone
+    {
+        AsyncLazyMap { state: AsyncState::with_policy(data, func, policy) }
+    }
+}
+impl<A, B, F, T> AsyncBag<T> for AsyncLazyMap<A, B, F>
+    where A: Send + 'static,
+          B: Borrow<T> + Send + Sync + 'static,
+          F: FnOnce(A) -> B + Send + 'static,
+          T: ?Sized + Sync,
+{
+    fn load<'a>(&'a self) -> Load<'a, T> {
+        fn poll<'a, A, B, F, T>(state: &'a AsyncState<A, B, F>, cx: &mut Context)
+            -> Poll<Result<&'a T, &'a fail::Error>>
+            where A: Send + 'static,
+                  B: Borrow<T> + Send + Sync + 'static,
+                  F: FnOnce(A) -> B + Send + 'static,
+                  T: ?Sized,
+        {
+            state.poll(cx).map(|data| data.map(Borrow::borrow))
+        }
+
+        Box::pin(Loading { state: &self.state, poll: poll::<A, B, F, T>, _ph: PhantomData })
+    }
+}
+
+/// Like `TryLazyMap`, but the function is applied on a shared worker thread,
+/// and the data is awaited through `AsyncBag`.
+pub struct AsyncTryLazyMap<A, B, F: FnOnce(A) -> Result<B, fail::Error>> {
+    state: AsyncState<A, Result<B, fail::Error>, F>,
+}
+impl<A, B, F> AsyncTryLazyMap<A, B, F>
+    where A: Send + 'static,
+          B: Send + Sync + 'static,
+          F: FnOnce(A) -> Result<B, fail::Error> + Send + 'static,
+{
+    pub fn new(data: A, func: F) -> Self {
+        AsyncTryLazyMap { state: AsyncState::new(data, func) }
+    }
+
+    /// Create a map that acts on panics by the given policy, like
+    /// `AsyncLazyMap::with_policy`. Errors returned by the function are kept,
+    /// whatever the policy.
+    pub fn with_policy(data: A, func: F, policy: PanicPolicy) -> Self
+        where A: Clone, F: Clone
+    {
+        AsyncTryLazyMap { state: AsyncState::with_policy(data, func, policy) }
+    }
+}
+impl<A, B, F, T> AsyncBag<T> for AsyncTryLazyMap<A, B, F>
+    where A: Send + 'static,
+          B: Borrow<T> + Send + Sync + 'static,
+          F: FnOnce(A) -> Result<B, fail::Error> + Send + 'static,
+          T: ?Sized + Sync,
+{
+    fn load<'a>(&'a self) -> Load<'a, T> {
+        fn poll<'a, A, B, F, T>(
+            state: &'a AsyncState<A, Result<B, fail::Error>, F>,
+            cx: &mut Context,
+        ) -> Poll<Result<&'a T, &'a fail::Error>>
+            where A: Send + 'static,
+                  B: Borrow<T> + Send + Sync + 'static,
+                  F: FnOnce(A) -> Result<B, fail::Error> + Send + 'static,
+                  T: ?Sized,
+        {
+            state.poll(cx).map(|data| data.and_then(Result::as_ref).map(Borrow::borrow))
+        }
+
+        Box::pin(Loading { state: &self.state, poll: poll::<A, B, F, T>, _ph: PhantomData })
+    }
+}
@@ -0,0 +1,75 @@
+use std::ptr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+struct Link<T> {
+    item: T,
+    next: OnceLock<Box<Link<T>>>,
+}
+
+/// A list which is only ever appended to, through a shared reference. Items
+/// never move, so borrows of them last as long as the list.
+pub struct Chain<T> {
+    head: OnceLock<Box<Link<T>>>,
+    /// A recent link, so that neither pushing nor reading the last item walks
+    /// the whole list. Links added since are found by following it.
+    tail: AtomicPtr<Link<T>>,
+}
+
+impl<T> Chain<T> {
+    pub fn new() -> Chain<T> {
+        Chain { head: OnceLock::new(), tail: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    fn tail(&self) -> Option<&Link<T>> {
+        // links are boxed, and only dropped along with the list
+        unsafe { self.tail.load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Add an item to the end of the list, and borrow it.
+    pub fn push(&self, item: T) -> &T {
+        let mut slot = match self.tail() {
+            Some(link) => &link.next,
+            None => &self.head,
+        };
+        let mut link = Box::new(Link { item, next: OnceLock::new() });
+        loop {
+            // another thread may have appended first
+            match slot.set(link) {
+                Ok(()) => {
+                    let added = slot.get().unwrap();
+                    self.tail.store(&**added as *const Link<T> as *mut Link<T>, Ordering::Release);
+                    return &added.item
+                },
+                Err(back) => {
+                    link = back;
+                    slot = &slot.get().unwrap().next;
+                },
+            }
+        }
+    }
+
+    /// The item added last.
+    pub fn last(&self) -> Option<&T> {
+        let mut link = match self.tail() {
+            Some(link) => link,
+            None => self.head.get()?,
+        };
+        while let Some(next) = link.next.get() {
+            link = next;
+        }
+        Some(&link.item)
+    }
+
+    /// Take the item added last, dropping the others.
+    pub fn into_last(self) -> Option<T> {
+        let mut link = self.head.into_inner()?;
+        loop {
+            let Link { item, next } = *link;
+            match next.into_inner() {
+                Some(next) => link = next,
+                None => return Some(item),
+            }
+        }
+    }
+}
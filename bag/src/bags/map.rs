@@ -1,100 +1,207 @@
-use ::{Bag, TryBag, Unbag, TryUnbag, fail};
-use std::sync::Mutex;
+use ::{Bag, TryBag, AsyncBag, Load, Unbag, TryUnbag, fail};
+use super::{panic_error, clone_input, CloneInput};
+use super::chain::Chain;
+use std::sync::{Mutex, OnceLock, PoisonError};
 use std::borrow::Borrow;
+use std::future;
+use std::panic::{self, AssertUnwindSafe};
 
-enum MapState<A, B, F> {
-    Unapplied {
-        func: F,
-        data: A,
-    },
-    Temp, // prevent double free in the event of unwind
-    Applied {
-        data: B,
-    },
+/// What a lazy map does when its function panics. Either way, the panic
+/// becomes an error for whoever borrowed the data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Keep the error. The function never runs again.
+    Fail,
+    /// Run the function again on the next borrow, up to the given number of
+    /// times, before keeping the error.
+    Retry(usize),
+}
+
+struct Unapplied<A, F> {
+    input: Option<(A, F)>,
+    panics: usize,
+}
+
+/// Data which is mapped the first time it is borrowed. Once mapped, borrowing
+/// takes no lock.
+struct MapState<A, B, F> {
+    data: OnceLock<B>,
+    // only locked while mapping
+    unapplied: Mutex<Unapplied<A, F>>,
+    /// The error from each panicking attempt that was retried.
+    panics: Chain<fail::Error>,
+    /// The error from the panic that was not retried.
+    failed: OnceLock<fail::Error>,
+    /// Copies the input for attempts that may be retried.
+    retry: Option<CloneInput<A, F>>,
+    /// Most panics to retry.
+    retries: usize,
 }
 
 impl<A, B, F: FnOnce(A) -> B> MapState<A, B, F> {
-    fn apply(&mut self) {      
-        // guarantee no mutation once applied!!
-        if let &mut MapState::Applied { .. } = self { return }
+    fn new(data: A, func: F) -> Self {
+        MapState {
+            data: OnceLock::new(),
+            unapplied: Mutex::new(Unapplied { input: Some((data, func)), panics: 0 }),
+            panics: Chain::new(),
+            failed: OnceLock::new(),
+            retry: None,
+            retries: 0,
+        }
+    }
 
-        // not yet applied
-        use std::mem::replace;
-        let applied = match replace(self, MapState::Temp) {
-            MapState::Unapplied { func, data } => MapState::Applied { data: func(data) },
-            _ => unreachable!(),
-        };
-        replace(self, applied);
+    fn with_policy(data: A, func: F, policy: PanicPolicy) -> Self
+        where A: Clone, F: Clone
+    {
+        let mut state = MapState::new(data, func);
+        if let PanicPolicy::Retry(times) = policy {
+            state.retries = times;
+            state.retry = Some(clone_input::<A, F>);
+        }
+        state
     }
 
-    fn get(self) -> B {
-        match self {
-            MapState::Applied { data } => data,
-            MapState::Unapplied { func, data } => func(data),
-            _ => unreachable!(),
+    fn get(&self) -> Result<&B, &fail::Error> {
+        if let Some(data) = self.data.get() { return Ok(data) }
+        if let Some(err) = self.failed.get() { return Err(err) }
+
+        // other threads wait here until the data is mapped
+        let mut unapplied = self.unapplied.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(data) = self.data.get() { return Ok(data) }
+        if let Some(err) = self.failed.get() { return Err(err) }
+
+        let retry = match self.retry {
+            Some(copy) if unapplied.panics < self.retries => Some(copy),
+            _ => None,
+        };
+        let (data, func) = match retry {
+            Some(copy) => copy(unapplied.input.as_ref().expect("map applied twice")),
+            None => unapplied.input.take().expect("map applied twice"),
+        };
+        match panic::catch_unwind(AssertUnwindSafe(move || func(data))) {
+            Ok(data) => {
+                unapplied.input = None;
+                Ok(self.data.get_or_init(move || data))
+            },
+            Err(payload) => {
+                unapplied.panics += 1;
+                let err = panic_error(payload);
+                match retry {
+                    Some(_) => Err(self.panics.push(err)),
+                    None => Err(self.failed.get_or_init(move || err)),
+                }
+            },
         }
     }
 
-    fn get_ptr(&mut self) -> *const B {
-        self.apply();
-        match self {
-            &mut MapState::Applied { ref data } => data,
-            _ => unreachable!(),
+    fn into_inner(self) -> Result<B, fail::Error> {
+        let MapState { data, unapplied, failed, .. } = self;
+        if let Some(data) = data.into_inner() { return Ok(data) }
+
+        let unapplied = unapplied.into_inner().unwrap_or_else(PoisonError::into_inner);
+        match unapplied.input {
+            Some((data, func)) => panic::catch_unwind(AssertUnwindSafe(move || func(data)))
+                .map_err(panic_error),
+            None => Err(failed.into_inner().expect("map applied twice")),
         }
     }
 }
 
 pub struct LazyMap<A, B, F: FnOnce(A) -> B> {
-    state: Mutex<MapState<A, B, F>>,
+    state: MapState<A, B, F>,
 }
 impl<A, B, F: FnOnce(A) -> B> LazyMap<A, B, F> {
     pub fn new(data: A, func: F) -> Self {
-        LazyMap { state: Mutex::new(MapState::Unapplied { data, func }) }
+        LazyMap { state: MapState::new(data, func) }
+    }
+
+    /// Create a map that acts on panics by the given policy.
+    pub fn with_policy(data: A, func: F, policy: PanicPolicy) -> Self
+        where A: Clone, F: Clone
+    {
+        LazyMap { state: MapState::with_policy(data, func, policy) }
     }
 }
-impl<A, B, F: FnOnce(A) -> B, T> Bag<T> for LazyMap<A, B, F> 
-    where T: ?Sized, B: Borrow<T> 
+/// Panics if the function panicked.
+impl<A, B, F: FnOnce(A) -> B, T> Bag<T> for LazyMap<A, B, F>
+    where T: ?Sized, B: Borrow<T>
 {
     fn get(&self) -> &T {
-        // Applied state will never be mutated, so we can borrow freely 
-        unsafe { &*self.state.lock().unwrap().get_ptr() }.borrow()
+        match self.state.get() {
+            Ok(data) => data.borrow(),
+            Err(e) => panic!("{}", e),
+        }
     }
 }
-impl<A, B, F: FnOnce(A) -> B, T> TryBag<T> for LazyMap<A, B, F> 
-    where T: ?Sized, B: Borrow<T> 
+impl<A, B, F: FnOnce(A) -> B, T> TryBag<T> for LazyMap<A, B, F>
+    where T: ?Sized, B: Borrow<T>
 {
     fn try_get(&self) -> Result<&T, &fail::Error> {
-        Ok(self.get())
+        self.state.get().map(Borrow::borrow)
     }
 }
+/// Blocks: the function runs when `load` is first called, on the caller's
+/// thread. Use `AsyncLazyMap` to load without blocking.
+impl<A, B, F: FnOnce(A) -> B, T> AsyncBag<T> for LazyMap<A, B, F>
+    where T: ?Sized + Sync, B: Borrow<T>
+{
+    fn load<'a>(&'a self) -> Load<'a, T> {
+        Box::pin(future::ready(self.try_get()))
+    }
+}
+/// Panics if the function panicked.
 impl<A, B, F: FnOnce(A) -> B> Unbag<B> for LazyMap<A, B, F> {
-    fn unbag(self) -> B { self.state.into_inner().unwrap().get() }
+    fn unbag(self) -> B {
+        match self.state.into_inner() {
+            Ok(data) => data,
+            Err(e) => panic!("{}", e),
+        }
+    }
 }
 impl<A, B, F: FnOnce(A) -> B> TryUnbag<B> for LazyMap<A, B, F> {
-    fn try_unbag(self) -> Result<B, fail::Error> { Ok(self.unbag()) }
+    fn try_unbag(self) -> Result<B, fail::Error> { self.state.into_inner() }
 }
 
 pub struct TryLazyMap<A, B, F: FnOnce(A) -> Result<B, fail::Error>> {
-    state: Mutex<MapState<
+    state: MapState<
         A,
         Result<B, fail::Error>,
         F
-    >>,
+    >,
 }
 impl<A, B, F: FnOnce(A) -> Result<B, fail::Error>> TryLazyMap<A, B, F> {
     pub fn new(data: A, func: F) -> Self {
-        TryLazyMap { state: Mutex::new(MapState::Unapplied { data, func }) }
+        TryLazyMap { state: MapState::new(data, func) }
+    }
+
+    /// Create a map that acts on panics by the given policy. Errors returned
+    /// by the function are kept, whatever the policy.
+    pub fn with_policy(data: A, func: F, policy: PanicPolicy) -> Self
+        where A: Clone, F: Clone
+    {
+        TryLazyMap { state: MapState::with_policy(data, func, policy) }
     }
 }
-impl<A, B, F: FnOnce(A) -> Result<B, fail::Error>, T> TryBag<T> for TryLazyMap<A, B, F> 
-    where T: ?Sized, B: Borrow<T> 
+impl<A, B, F: FnOnce(A) -> Result<B, fail::Error>, T> TryBag<T> for TryLazyMap<A, B, F>
+    where T: ?Sized, B: Borrow<T>
 {
     fn try_get(&self) -> Result<&T, &fail::Error> {
-        unsafe { &*self.state.lock().unwrap().get_ptr() }.as_ref().map(Borrow::borrow)
+        self.state.get()
+            .and_then(Result::as_ref)
+            .map(Borrow::borrow)
+    }
+}
+/// Blocks: the function runs when `load` is first called, on the caller's
+/// thread. Use `AsyncTryLazyMap` to load without blocking.
+impl<A, B, F: FnOnce(A) -> Result<B, fail::Error>, T> AsyncBag<T> for TryLazyMap<A, B, F>
+    where T: ?Sized + Sync, B: Borrow<T>
+{
+    fn load<'a>(&'a self) -> Load<'a, T> {
+        Box::pin(future::ready(self.try_get()))
     }
 }
 impl<A, B, F: FnOnce(A) -> Result<B, fail::Error>> TryUnbag<B> for TryLazyMap<A, B, F> {
     fn try_unbag(self) -> Result<B, fail::Error> {
-        self.state.into_inner().unwrap().get()
+        self.state.into_inner()?
     }
 }
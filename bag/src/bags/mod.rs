@@ -1,8 +1,36 @@
-use ::{Bag, TryBag, Unbag, TryUnbag, fail};
+use ::{Bag, TryBag, AsyncBag, Load, Unbag, TryUnbag, fail};
+use std::any::Any;
 use std::borrow::Borrow;
+use std::future;
 
+mod chain;
 mod map;
 pub use self::map::*;
+mod async_map;
+pub use self::async_map::*;
+mod retry;
+pub use self::retry::*;
+mod reload;
+pub use self::reload::*;
+
+/// Turn the payload of a caught panic into an error.
+fn panic_error(payload: Box<Any + Send>) -> fail::Error {
+    let msg = match payload.downcast_ref::<&str>() {
+        Some(msg) => msg.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None => "unknown panic".to_owned(),
+        },
+    };
+    format_err!("loader panicked: {}", msg)
+}
+
+/// Copies the input of a lazy map, for attempts that may be retried.
+type CloneInput<A, F> = fn(&(A, F)) -> (A, F);
+
+fn clone_input<A: Clone, F: Clone>(input: &(A, F)) -> (A, F) {
+    input.clone()
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Static<U: ?Sized>(pub U);
@@ -14,6 +42,12 @@ impl<T: ?Sized, U: ?Sized + Borrow<T>> TryBag<T> for Static<U> {
         Ok(self.get())
     }
 }
+/// Resolves at once.
+impl<T: ?Sized + Sync, U: ?Sized + Borrow<T>> AsyncBag<T> for Static<U> {
+    fn load<'a>(&'a self) -> Load<'a, T> {
+        Box::pin(future::ready(Ok(self.get())))
+    }
+}
 impl<U> Unbag<U> for Static<U> {
     fn unbag(self) -> U { self.0 }
 }
@@ -0,0 +1,152 @@
+use ::{TryBag, TryUnbag, fail};
+use super::panic_error;
+use super::chain::Chain;
+use std::borrow::Borrow;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Modification time and length of a file, if it exists.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    fs::metadata(path).ok()
+        .and_then(|m| m.modified().ok().map(|t| (t, m.len())))
+}
+
+struct Watch {
+    /// The state of the file when it was last loaded.
+    stamp: Stamp,
+    /// When the file was last checked for changes.
+    checked: Option<Instant>,
+    /// Whether a reload is running.
+    loading: bool,
+    generation: u64,
+}
+
+/// Like `TryLazyMap`, but the function runs again whenever the file it reads
+/// changes. The file is polled, no more often than the interval, when the data
+/// is borrowed.
+///
+/// Changes are noticed by the modification time and length of the file, so
+/// that polling does not read it. An edit that keeps both, such as one of the
+/// same length saved within the timestamp resolution of the file system, is
+/// missed until the file changes again.
+///
+/// Every load is kept until the map is dropped, since earlier borrows may
+/// still use it. This is meant for assets edited during development, not for
+/// files that change all the time. While the data is reloaded, readers get
+/// the previous load.
+pub struct ReloadMap<A, B, F: Fn(&A) -> Result<B, fail::Error>> {
+    input: A,
+    func: F,
+    /// The file read by the function.
+    watch: fn(&A) -> PathBuf,
+    interval: Duration,
+    /// Every load, so that data borrowed from an earlier one stays valid.
+    loads: Chain<Result<B, fail::Error>>,
+    state: Mutex<Watch>,
+}
+
+impl<A, B, F: Fn(&A) -> Result<B, fail::Error>> ReloadMap<A, B, F> {
+    /// Create a map which checks the file named by `watch` at most twice a
+    /// second.
+    pub fn new(input: A, watch: fn(&A) -> PathBuf, func: F) -> Self {
+        ReloadMap {
+            input,
+            func,
+            watch,
+            interval: Duration::from_millis(500),
+            loads: Chain::new(),
+            state: Mutex::new(Watch {
+                stamp: None,
+                checked: None,
+                loading: false,
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Check the file at most once per `interval`.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Number of times the data has been loaded. Readers can keep the
+    /// generation they saw, and compare it later to notice a reload.
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Check the file now, whatever the interval, and reload it if it has
+    /// changed. Returns the generation afterwards, which does not yet count a
+    /// reload started by another thread.
+    pub fn poll(&self) -> u64 {
+        self.check(self.lock())
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, Watch> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Reload the file if it changed, and return the generation. Only the
+    /// first load holds the lock while the function runs, since there is no
+    /// earlier load to give readers in the meantime.
+    fn check(&self, mut state: MutexGuard<Watch>) -> u64 {
+        state.checked = Some(Instant::now());
+        let stamp = stamp(&(self.watch)(&self.input));
+        if state.generation > 0 {
+            // editors may briefly remove a file while saving it
+            if state.loading || stamp.is_none() || stamp == state.stamp {
+                return state.generation
+            }
+        }
+        state.stamp = stamp;
+        state.loading = true;
+        let held = if state.generation == 0 {
+            Some(state)
+        } else {
+            drop(state);
+            None
+        };
+
+        let data = panic::catch_unwind(AssertUnwindSafe(|| (self.func)(&self.input)))
+            .unwrap_or_else(|payload| Err(panic_error(payload)));
+        self.loads.push(data);
+
+        let mut state = held.unwrap_or_else(|| self.lock());
+        state.loading = false;
+        state.generation += 1;
+        state.generation
+    }
+
+    fn load(&self) -> &Result<B, fail::Error> {
+        let state = self.lock();
+        let due = state.checked
+            .map(|checked| checked.elapsed() >= self.interval)
+            .unwrap_or(true);
+        if due {
+            self.check(state);
+        } else {
+            drop(state);
+        }
+        self.loads.last().expect("map was never loaded")
+    }
+}
+
+impl<A, B, F: Fn(&A) -> Result<B, fail::Error>, T> TryBag<T> for ReloadMap<A, B, F>
+    where T: ?Sized, B: Borrow<T>
+{
+    fn try_get(&self) -> Result<&T, &fail::Error> {
+        self.load().as_ref().map(Borrow::borrow)
+    }
+}
+impl<A, B, F: Fn(&A) -> Result<B, fail::Error>> TryUnbag<B> for ReloadMap<A, B, F> {
+    fn try_unbag(self) -> Result<B, fail::Error> {
+        self.load();
+        self.loads.into_last().expect("map was never loaded")
+    }
+}
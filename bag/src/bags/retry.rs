@@ -0,0 +1,160 @@
+use ::{TryBag, TryUnbag, fail};
+use super::panic_error;
+use super::chain::Chain;
+use std::borrow::Borrow;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+/// When a `RetryMap` runs its function again after a failure.
+///
+/// Only I/O errors are retried, and not those of kind `InvalidData`, unless
+/// `retry_decode_errors` is set. A function that panics is never run again,
+/// whatever the policy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Most times to run the function.
+    pub max_attempts: usize,
+    /// Time to wait after the first failure. Each later wait is twice as long.
+    pub backoff: Duration,
+    /// Longest time to wait between attempts.
+    pub max_backoff: Duration,
+    /// Also retry errors that are not I/O errors, such as data that fails to
+    /// decode. Invalid data read from a file counts as a decode error.
+    pub retry_decode_errors: bool,
+}
+
+impl RetryPolicy {
+    /// Retry I/O errors until the function has run the given number of times.
+    pub fn attempts(max_attempts: usize) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            retry_decode_errors: false,
+        }
+    }
+
+    /// Should an attempt that failed with this error be retried?
+    fn retries(&self, err: &fail::Error) -> bool {
+        if self.retry_decode_errors { return true }
+        match err.downcast_ref::<io::Error>() {
+            Some(e) => e.kind() != io::ErrorKind::InvalidData,
+            None => false,
+        }
+    }
+
+    /// How long to wait after the given number of failed attempts.
+    fn wait(&self, failures: usize) -> Duration {
+        let doublings = failures.saturating_sub(1).min(31) as u32;
+        self.backoff
+            .checked_mul(1 << doublings)
+            .map(|d| d.min(self.max_backoff))
+            .unwrap_or(self.max_backoff)
+    }
+}
+
+/// Tries three times.
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::attempts(3)
+    }
+}
+
+struct Attempts {
+    count: usize,
+    /// No attempt runs before this time.
+    next: Option<Instant>,
+    gave_up: bool,
+}
+
+/// Like `TryLazyMap`, but a failed function is run again on a later borrow,
+/// according to a `RetryPolicy`. Borrows between attempts get the last error.
+pub struct RetryMap<A, B, F: Fn(&A) -> Result<B, fail::Error>> {
+    input: A,
+    func: F,
+    policy: RetryPolicy,
+    data: OnceLock<B>,
+    /// The error from each failed attempt.
+    errors: Chain<fail::Error>,
+    attempts: Mutex<Attempts>,
+}
+
+impl<A, B, F: Fn(&A) -> Result<B, fail::Error>> RetryMap<A, B, F> {
+    pub fn new(input: A, func: F, policy: RetryPolicy) -> Self {
+        RetryMap {
+            input,
+            func,
+            policy,
+            data: OnceLock::new(),
+            errors: Chain::new(),
+            attempts: Mutex::new(Attempts { count: 0, next: None, gave_up: false }),
+        }
+    }
+
+    /// Number of times the function has run.
+    pub fn attempts(&self) -> usize {
+        self.lock().count
+    }
+
+    /// The error from the last failed attempt, if any.
+    pub fn last_error(&self) -> Option<&fail::Error> {
+        self.errors.last()
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, Attempts> {
+        self.attempts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn load(&self) -> Result<&B, &fail::Error> {
+        if let Some(data) = self.data.get() { return Ok(data) }
+
+        // other threads wait here while the function runs
+        let mut attempts = self.lock();
+        if let Some(data) = self.data.get() { return Ok(data) }
+        let waiting = attempts.next.map(|next| Instant::now() < next).unwrap_or(false);
+        if attempts.gave_up || waiting {
+            return Err(self.errors.last().expect("failed attempt has no error"))
+        }
+
+        attempts.count += 1;
+        let (result, panicked) = match panic::catch_unwind(AssertUnwindSafe(|| (self.func)(&self.input))) {
+            Ok(result) => (result, false),
+            Err(payload) => (Err(panic_error(payload)), true),
+        };
+        match result {
+            Ok(data) => Ok(self.data.get_or_init(move || data)),
+            Err(err) => {
+                if panicked
+                    || attempts.count >= self.policy.max_attempts
+                    || !self.policy.retries(&err)
+                {
+                    attempts.gave_up = true;
+                } else {
+                    attempts.next = Some(Instant::now() + self.policy.wait(attempts.count));
+                }
+                Err(self.errors.push(err))
+            },
+        }
+    }
+}
+
+impl<A, B, F: Fn(&A) -> Result<B, fail::Error>, T> TryBag<T> for RetryMap<A, B, F>
+    where T: ?Sized, B: Borrow<T>
+{
+    fn try_get(&self) -> Result<&T, &fail::Error> {
+        self.load().map(Borrow::borrow)
+    }
+}
+impl<A, B, F: Fn(&A) -> Result<B, fail::Error>> TryUnbag<B> for RetryMap<A, B, F> {
+    fn try_unbag(self) -> Result<B, fail::Error> {
+        // give a load that is due its chance
+        let _ = self.load();
+        let RetryMap { data, errors, .. } = self;
+        match data.into_inner() {
+            Some(data) => Ok(data),
+            None => Err(errors.into_last().expect("failed attempt has no error")),
+        }
+    }
+}
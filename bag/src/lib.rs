@@ -9,16 +9,40 @@
 //! through plugins (i.e. `bagger_image`, `bagger_json`, `bagger_js`, `bagger_dll`).
 //! Most of these plugins do have a runtime dependency that must also be imported (`image`, `serde_json`, `jsbag`, `sharedlib`). 
 //! Bagger will warn if any of these are missing.
+//!
+//! ## Targets and profiles
+//!
+//! A `Bag.toml` next to `Cargo.toml` can change how assets are bagged for
+//! each target (`[target."cfg(...)"]`) and profile (`[profile.release]`).
+//! Cargo only tells build scripts which target is being built, so a crate
+//! with `[target]` sections needs a `build.rs` that passes it on to `bag!`:
+//!
+//! ```ignore
+//! fn main() {
+//!     let target = std::env::var("TARGET").unwrap();
+//!     println!("cargo:rustc-env=TARGET={}", target);
+//! }
+//! ```
+//!
+//! Without it, every `bag!` in the crate fails to compile. The profile can be
+//! passed on the same way (`PROFILE`). If it is not, `bag!` bags the asset for
+//! both `dev` and `release`, and picks by `cfg(debug_assertions)`.
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[macro_use]
 pub extern crate failure as fail;
+#[macro_use]
+extern crate lazy_static;
 
+pub mod alias;
 pub mod bags;
 pub mod ops;
 pub mod macros;
 
+use std::future::Future;
 use std::ops::Deref;
+use std::pin::Pin;
 
 /// Trait for types that provide access to some data.
 pub trait Bag<T: ?Sized>: TryBag<T> {
@@ -41,6 +65,17 @@ pub trait TryBag<T: ?Sized> {
     fn try_get(&self) -> Result<&T, &fail::Error>;
 }
 
+/// A future which resolves to some stored data, or an error if the data failed
+/// to load.
+pub type Load<'a, T> = Pin<Box<Future<Output = Result<&'a T, &'a fail::Error>> + Send + 'a>>;
+
+/// Trait for types that load some data without blocking the caller.
+pub trait AsyncBag<T: ?Sized> {
+    /// Start loading the data, if it is not loaded already. The returned
+    /// future resolves once the data is available.
+    fn load<'a>(&'a self) -> Load<'a, T>;
+}
+
 /// Trait for types that wrap some data.
 pub trait Unbag<T>: TryUnbag<T> {
     /// Unwrap the stored data.
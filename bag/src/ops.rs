@@ -1,21 +1,32 @@
 use ::{Unbag, TryUnbag, fail};
-use ::bags::{LazyMap, TryLazyMap};
+use ::bags::{LazyMap, TryLazyMap, AsyncTryLazyMap, RetryMap, RetryPolicy, ReloadMap};
+use ::alias::RuntimePath;
 use std::path::Path;
 use std::io::Read;
 use std::fs::File;
 
-pub fn map<A, B, T: Unbag<A>, F: FnOnce(A) -> B>(bag: T, func: F)
-        -> LazyMap<(T, F), B, fn((T, F))->B>
-{
+/// A bag mapped by `map`.
+pub type Mapped<T, F, B> = LazyMap<(T, F), B, fn((T, F)) -> B>;
+
+pub fn map<A, B, T: Unbag<A>, F: FnOnce(A) -> B>(bag: T, func: F) -> Mapped<T, F, B> {
     LazyMap::new((bag, func), |(bag, func)| func(bag.unbag()))
 }
 
+/// A bag mapped by `try_map`.
+pub type TryMapped<T, F, B> = TryLazyMap<(T, F), B, fn((T, F)) -> Result<B, fail::Error>>;
+
 pub fn try_map<A, B, T: TryUnbag<A>, F: FnOnce(A) -> Result<B, fail::Error>>(bag: T, func: F)
-        -> TryLazyMap<(T, F), B, fn((T, F))->Result<B, fail::Error>>
+        -> TryMapped<T, F, B>
 {
     TryLazyMap::new((bag, func), |(bag, func)| func(bag.try_unbag()?))
 }
 
+/// Trait for types which decode raw asset data. These are named by
+/// `[format."Type"]` tables in `Bag.toml`.
+pub trait Loader<T> {
+    fn load(bytes: &[u8]) -> Result<T, fail::Error>;
+}
+
 pub trait ReadTarget: Sized {
     fn consume<R: Read>(R) -> Result<Self, fail::Error>;
 }
@@ -36,8 +47,10 @@ impl ReadTarget for Vec<u8> {
     }
 }
 
-pub fn file_contents<P, T>(path: P)
-    -> TryLazyMap<P, T, fn(P) -> Result<T, fail::Error>>
+/// A bag which loads a file when first borrowed.
+pub type FileContents<P, T> = TryLazyMap<P, T, fn(P) -> Result<T, fail::Error>>;
+
+pub fn file_contents<P, T>(path: P) -> FileContents<P, T>
     where P: AsRef<Path>, T: ReadTarget
 {
     TryLazyMap::new(path, |path| {
@@ -45,3 +58,64 @@ pub fn file_contents<P, T>(path: P)
         T::consume(file)
     })
 }
+
+/// A bag which loads a file when first borrowed, and again whenever it
+/// changes.
+pub type ReloadingFileContents<P, T> = ReloadMap<P, T, fn(&P) -> Result<T, fail::Error>>;
+
+/// Like `file_contents`, but reloads the file whenever it changes.
+pub fn reloading_file_contents<P, T>(path: P) -> ReloadingFileContents<P, T>
+    where P: AsRef<Path>, T: ReadTarget
+{
+    ReloadMap::new(path, |path| path.as_ref().to_owned(), |path| {
+        let file = File::open(path)?;
+        T::consume(file)
+    })
+}
+
+/// A bag which loads a file at run time.
+pub type RuntimeFile<T> = TryLazyMap<RuntimePath, T, fn(RuntimePath) -> Result<T, fail::Error>>;
+
+pub fn runtime_file<T: ReadTarget>(path: RuntimePath) -> RuntimeFile<T> {
+    TryLazyMap::new(path, |path| {
+        let file = File::open(path.resolve())?;
+        T::consume(file)
+    })
+}
+
+/// A bag which loads a file at run time, trying again after a failure.
+pub type RetryingRuntimeFile<T> =
+    RetryMap<RuntimePath, T, fn(&RuntimePath) -> Result<T, fail::Error>>;
+
+pub fn retrying_runtime_file<T: ReadTarget>(path: RuntimePath, policy: RetryPolicy)
+    -> RetryingRuntimeFile<T>
+{
+    RetryMap::new(path, |path| {
+        let file = File::open(path.resolve())?;
+        T::consume(file)
+    }, policy)
+}
+
+/// A bag which loads a file at run time, on its own thread.
+pub type AsyncRuntimeFile<T> =
+    AsyncTryLazyMap<RuntimePath, T, fn(RuntimePath) -> Result<T, fail::Error>>;
+
+pub fn async_runtime_file<T>(path: RuntimePath) -> AsyncRuntimeFile<T>
+    where T: ReadTarget + Send + Sync + 'static
+{
+    AsyncTryLazyMap::new(path, |path| {
+        let file = File::open(path.resolve())?;
+        T::consume(file)
+    })
+}
+
+/// A bag which loads a file at run time, and again whenever it changes.
+pub type ReloadingRuntimeFile<T> =
+    ReloadMap<RuntimePath, T, fn(&RuntimePath) -> Result<T, fail::Error>>;
+
+pub fn reloading_runtime_file<T: ReadTarget>(path: RuntimePath) -> ReloadingRuntimeFile<T> {
+    ReloadMap::new(path, RuntimePath::resolve, |path| {
+        let file = File::open(path.resolve())?;
+        T::consume(file)
+    })
+}
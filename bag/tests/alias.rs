@@ -0,0 +1,76 @@
+extern crate bag;
+use bag::alias::{AliasTable, Glob};
+
+use std::str::FromStr;
+use std::path::Path;
+
+#[test]
+fn glob_captures() {
+    let glob = Glob::from_str("image/**/*.png").unwrap();
+    assert_eq!(
+        glob.captures("image/ui/icons/close.png").unwrap(),
+        vec!["ui/icons".to_owned(), "close".to_owned()],
+    );
+    assert!(!glob.matches("image/close.jpg"));
+
+    let glob = Glob::from_str("*.txt").unwrap();
+    assert!(glob.matches("hello.txt"));
+    assert!(!glob.matches("dir/hello.txt"));
+
+    let glob = Glob::from_str("file?.txt").unwrap();
+    assert!(glob.matches("file1.txt"));
+    assert!(!glob.matches("file10.txt"));
+}
+
+#[test]
+fn glob_ambiguous() {
+    assert!(Glob::from_str("image/***").is_err());
+    assert!(Glob::from_str("image/**/**").is_ok());
+}
+
+#[test]
+fn alias_resolve() {
+    let mut table = AliasTable::new();
+    table.insert("image/**", "public/img/**").unwrap();
+    assert_eq!(table.resolve("image/logo.png").unwrap(), "public/img/logo.png");
+    assert_eq!(table.resolve("image/a/b.png").unwrap(), "public/img/a/b.png");
+    assert_eq!(table.resolve("LICENSE"), None);
+}
+
+#[test]
+fn alias_most_specific() {
+    let mut table = AliasTable::new();
+    table.insert("**", "root/**").unwrap();
+    table.insert("image/**", "public/img/**").unwrap();
+    table.insert("image/*.svg", "vector/*.svg").unwrap();
+    table.insert("image/logo.png", "brand/logo.png").unwrap();
+
+    assert_eq!(table.resolve("image/logo.png").unwrap(), "brand/logo.png");
+    assert_eq!(table.resolve("image/icon.svg").unwrap(), "vector/icon.svg");
+    assert_eq!(table.resolve("image/icon.png").unwrap(), "public/img/icon.png");
+    assert_eq!(table.resolve("LICENSE").unwrap(), "root/LICENSE");
+}
+
+#[test]
+fn alias_mismatched_wildcards() {
+    let mut table = AliasTable::new();
+    assert!(table.insert("image/**", "logo.png").is_err());
+}
+
+#[test]
+fn runtime_path_override() {
+    use bag::TryBag;
+    use bag::alias::{RuntimePath, set_runtime_aliases};
+    use bag::ops::runtime_file;
+
+    let path = RuntimePath::new("greeting/hello.txt", "./missing/hello.txt");
+    assert_eq!(path.resolve(), Path::new("./missing/hello.txt"));
+
+    let mut table = AliasTable::new();
+    table.insert("greeting/**", "./tests/**").unwrap();
+    set_runtime_aliases(table);
+    assert_eq!(path.resolve(), Path::new("./tests/hello.txt"));
+
+    let bag = runtime_file::<String>(path);
+    assert_eq!(TryBag::<str>::try_get(&bag).unwrap(), "Hello, world!");
+}
@@ -1,11 +1,16 @@
 extern crate bag;
-use bag::{Bag, TryBag, Unbag, TryUnbag};
+use bag::{Bag, TryBag, Unbag, TryUnbag, AsyncBag};
 use bag::bags::*;
 use bag::ops::*;
 
 #[macro_use]
 extern crate failure;
 
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
 pub static STATIC_TEXT: Static<&str> = Static(HELLO);
 pub const HELLO: &str = "Hello, world!";
 
@@ -70,3 +75,217 @@ fn lazy_map_is_lazy() {
     Bag::<()>::get(&bag);
     assert!(atom.load(Ordering::SeqCst));
 }
+
+#[test]
+fn lazy_map_loads_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let bag_count = count.clone();
+    let bag = Arc::new(map(Static(7), move |x| {
+        bag_count.fetch_add(1, Ordering::SeqCst);
+        x * 6
+    }));
+
+    let threads: Vec<_> = (0..8).map(|_| {
+        let bag = bag.clone();
+        thread::spawn(move || *Bag::<u32>::get(&*bag))
+    }).collect();
+    for t in threads {
+        assert_eq!(t.join().unwrap(), 42);
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn lazy_map_panic() {
+    let bag = try_map(Static("oops"), |s: &str| -> Result<u32, _> { panic!("{}", s) });
+    let err = TryBag::<u32>::try_get(&bag).unwrap_err();
+    assert_eq!(err.to_string(), "loader panicked: oops");
+    // the error is kept, and later borrows do not panic
+    assert_eq!(TryBag::<u32>::try_get(&bag).unwrap_err().to_string(), err.to_string());
+    assert!(bag.try_unbag().is_err());
+
+    let bag = map(Static(()), |()| -> u32 { panic!("oops") });
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+}
+
+#[test]
+fn lazy_map_retry_panic() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let bag = TryLazyMap::with_policy(count.clone(), |count: Arc<AtomicUsize>| {
+        if count.fetch_add(1, Ordering::SeqCst) < 2 { panic!("not yet") }
+        Ok(42)
+    }, PanicPolicy::Retry(2));
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert_eq!(*TryBag::<u32>::try_get(&bag).unwrap(), 42);
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+
+    let bag = LazyMap::with_policy((), |()| -> u32 { panic!("never") }, PanicPolicy::Retry(1));
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    // no more retries
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let bag = LazyMap::with_policy(count.clone(), |count: Arc<AtomicUsize>| -> u32 {
+        if count.fetch_add(1, Ordering::SeqCst) < 3 { panic!("not yet") }
+        7
+    }, PanicPolicy::Retry(usize::MAX));
+    for _ in 0..3 {
+        assert!(TryBag::<u32>::try_get(&bag).is_err());
+    }
+    assert_eq!(*TryBag::<u32>::try_get(&bag).unwrap(), 7);
+}
+
+#[test]
+fn async_lazy_map_panic() {
+    let bag = AsyncLazyMap::new((), |()| -> u32 { panic!("oops") });
+    let err = block_on(AsyncBag::<u32>::load(&bag)).unwrap_err();
+    assert_eq!(err.to_string(), "loader panicked: oops");
+}
+
+#[test]
+fn async_lazy_map_retry_panic() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let bag = AsyncTryLazyMap::with_policy(count.clone(), |count: Arc<AtomicUsize>| {
+        if count.fetch_add(1, Ordering::SeqCst) < 2 { panic!("not yet") }
+        Ok(42)
+    }, PanicPolicy::Retry(2));
+    // retried before the first load finishes
+    assert_eq!(*block_on(AsyncBag::<u32>::load(&bag)).unwrap(), 42);
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+
+    let bag = AsyncLazyMap::with_policy((), |()| -> u32 { panic!("never") }, PanicPolicy::Retry(1));
+    let err = block_on(AsyncBag::<u32>::load(&bag)).unwrap_err();
+    assert_eq!(err.to_string(), "loader panicked: never");
+}
+
+struct Unpark(Thread);
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) { self.0.unpark() }
+}
+
+/// Run a future to completion on the current thread.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(out) => return out,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn async_lazy_map() {
+    let bag = AsyncLazyMap::new(99, |x| format!("{} bottles", x));
+    assert_eq!(block_on(AsyncBag::<str>::load(&bag)).unwrap(), "99 bottles");
+    // loaded only once
+    assert_eq!(block_on(AsyncBag::<str>::load(&bag)).unwrap(), "99 bottles");
+}
+
+#[test]
+fn async_try_lazy_map() {
+    use std::str::FromStr;
+
+    let bag = AsyncTryLazyMap::new("hello", |s| Ok(u32::from_str(s)?));
+    assert!(block_on(AsyncBag::<u32>::load(&bag)).is_err());
+
+    let bag = AsyncTryLazyMap::new("42", |s| Ok(u32::from_str(s)?));
+    assert_eq!(*block_on(AsyncBag::<u32>::load(&bag)).unwrap(), 42);
+}
+
+#[test]
+fn async_static() {
+    let bag = Static("hello");
+    assert_eq!(block_on(AsyncBag::<str>::load(&bag)).unwrap(), "hello");
+
+    let bag = LazyMap::new(99, |x| format!("{} bottles", x));
+    assert_eq!(block_on(AsyncBag::<str>::load(&bag)).unwrap(), "99 bottles");
+
+    use std::str::FromStr;
+    let bag = TryLazyMap::new("hello", |s| Ok(u32::from_str(s)?));
+    assert!(block_on(AsyncBag::<u32>::load(&bag)).is_err());
+}
+
+#[test]
+fn async_runtime_text() {
+    use bag::alias::RuntimePath;
+
+    let bag = async_runtime_file::<String>(RuntimePath::new("./tests/hello.txt", "./tests/hello.txt"));
+    assert_eq!(block_on(AsyncBag::<str>::load(&bag)).unwrap(), "Hello, world!");
+}
+
+#[test]
+fn retry_map() {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let policy = RetryPolicy { backoff: Duration::from_millis(0), ..RetryPolicy::attempts(3) };
+    let bag = RetryMap::new(AtomicUsize::new(0), |count| {
+        if count.fetch_add(1, Ordering::SeqCst) < 2 {
+            Err(io::Error::new(io::ErrorKind::NotFound, "not yet"))?
+        }
+        Ok(42)
+    }, policy);
+    assert!(bag.last_error().is_none());
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert_eq!(bag.last_error().unwrap().to_string(), "not yet");
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert_eq!(*TryBag::<u32>::try_get(&bag).unwrap(), 42);
+    assert_eq!(bag.attempts(), 3);
+
+    // data that fails to decode is not retried
+    let bag = RetryMap::new((), |()| -> Result<u32, _> {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "garbled"))?
+    }, policy);
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert_eq!(bag.attempts(), 1);
+    assert!(bag.try_unbag().is_err());
+
+    // nor is a function that panics, even if every error is retried
+    let all = RetryPolicy { retry_decode_errors: true, ..policy };
+    let bag = RetryMap::new((), |()| -> Result<u32, _> { panic!("oops") }, all);
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert!(TryBag::<u32>::try_get(&bag).is_err());
+    assert_eq!(bag.attempts(), 1);
+
+    // nothing is kept for attempts that never happen
+    let bag = RetryMap::new((), |()| Ok(1), RetryPolicy::attempts(usize::MAX));
+    assert_eq!(*TryBag::<u32>::try_get(&bag).unwrap(), 1);
+}
+
+#[test]
+fn reloading_file() {
+    use std::{env, fs};
+
+    let path = env::temp_dir().join(format!("bag-reload-{}.txt", std::process::id()));
+    fs::write(&path, "one").unwrap();
+    let bag = reloading_file_contents::<_, String>(path.clone());
+    assert_eq!(bag.generation(), 0);
+    let first = TryBag::<str>::try_get(&bag).unwrap();
+    assert_eq!(first, "one");
+    assert_eq!(bag.generation(), 1);
+    assert_eq!(bag.poll(), 1);
+
+    fs::write(&path, "two!").unwrap();
+    assert_eq!(bag.poll(), 2);
+    assert_eq!(TryBag::<str>::try_get(&bag).unwrap(), "two!");
+    // data borrowed before the reload is kept
+    assert_eq!(first, "one");
+
+    // a missing file keeps the last load
+    fs::remove_file(&path).unwrap();
+    assert_eq!(bag.poll(), 2);
+    assert_eq!(bag.try_unbag().unwrap(), "two!");
+}
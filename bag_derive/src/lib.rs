@@ -16,7 +16,11 @@ extern crate proc_macro;
 extern crate proc_macro2;
 
 extern crate bagger;
-use bagger::{Bagger, BagRequest};
+extern crate failure;
+use bagger::{Bagger, BagRequest, SearchGraph, Diagnostic};
+use bagger::check::{DiagnosticKind, Severity};
+use bagger::config::watched_paths;
+use bagger::interp::interpolate;
 use bagger::Uri;
 use bagger::flag::{Flag, FlagSet, FlagMap};
 use bagger::expr::{BagInfo, BagTrait};
@@ -26,6 +30,180 @@ use proc_macro::TokenStream;
 use syn::visit::{self, Visit};
 
 use std::str::FromStr;
+use std::env;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeSet;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The bagger shared by every expansion on this thread, along with the
+/// manifests it was configured from. A compiler that expands macros on
+/// several threads has a bagger, and so a solution cache, on each.
+struct Shared {
+    dir: Option<OsString>,
+    /// Files that could change the manifests, and their state when loaded.
+    watched: Vec<PathBuf>,
+    stamps: Vec<Option<SystemTime>>,
+    bagger: Bagger,
+    /// Baggers for the `dev` and `release` profiles, if the manifests have
+    /// `[profile]` sections but the profile being built is not known.
+    profiles: Option<(Bagger, Bagger)>,
+    /// Problems found in the manifests.
+    diags: Vec<Diagnostic>,
+    /// Whether an expansion has reported the manifest warnings yet.
+    warned: Cell<bool>,
+}
+
+thread_local! {
+    static SHARED: RefCell<Option<Shared>> = RefCell::new(None);
+}
+
+fn stamps(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths.iter()
+        .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Diagnostics for manifests that could not be loaded. The manifests are
+/// checked for the cause, which is reported as a whole if checking finds
+/// nothing.
+fn load_errors(dir: Option<&OsString>, causes: Vec<String>) -> Vec<Diagnostic> {
+    let mut diags: Vec<_> = match dir {
+        Some(dir) => Bagger::new().check_manifests(dir),
+        None => Vec::new(),
+    };
+    diags.retain(Diagnostic::is_error);
+    if diags.is_empty() {
+        diags.push(Diagnostic {
+            severity: Severity::Error,
+            kind: DiagnosticKind::InvalidValue,
+            message: format!("could not load Bag.toml: {}", causes.join(": ")),
+            file: None,
+            line: None,
+        });
+    }
+    diags
+}
+
+/// A bagger like the given one, for a known profile.
+fn with_profile(bagger: &Bagger, profile: &str) -> Result<Bagger, failure::Error> {
+    let mut build = bagger.build().clone();
+    build.profile = Some(profile.to_owned());
+    let mut bagger = Bagger::with_config(bagger.config().clone())?;
+    bagger.set_build(build);
+    Ok(bagger)
+}
+
+/// Baggers for both profiles a build script does not need to tell apart, if
+/// the profile matters but is unknown. Setting `BAGGER_REQUIRE_PROFILE`
+/// makes an unknown profile an error instead.
+fn split_profiles(bagger: &Bagger) -> Result<Option<(Bagger, Bagger)>, failure::Error> {
+    if bagger.build().profile.is_some()
+        || bagger.config().profile.is_empty()
+        || env::var_os("BAGGER_REQUIRE_PROFILE").is_some()
+    {
+        return Ok(None)
+    }
+    Ok(Some((with_profile(bagger, "dev")?, with_profile(bagger, "release")?)))
+}
+
+/// Run a function with the shared bagger, creating it on first use. The
+/// bagger is created again if the crate or its manifests change, as they can
+/// when the compiler is kept running between builds. Manifests that can not
+/// be loaded are reported as errors, and loaded again on the next expansion.
+fn with_bagger<R, F>(func: F) -> Result<R, Vec<Diagnostic>>
+    where F: FnOnce(&Shared) -> R
+{
+    SHARED.with(|shared| {
+        let mut shared = shared.borrow_mut();
+        let dir = env::var_os("CARGO_MANIFEST_DIR");
+        let stale = match *shared {
+            Some(ref s) => s.dir != dir || s.stamps != stamps(&s.watched),
+            None => true,
+        };
+        if stale {
+            *shared = None;
+            let watched = match dir {
+                Some(ref dir) => watched_paths(dir),
+                None => Vec::new(),
+            };
+            // stamp before loading, so that a change made while loading is
+            // noticed next time
+            let loaded_stamps = stamps(&watched);
+            let loaded = Bagger::from_env()
+                .and_then(|b| split_profiles(&b).map(|p| (b, p)));
+            let (bagger, profiles) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    let causes = e.iter_chain().map(ToString::to_string).collect();
+                    return Err(load_errors(dir.as_ref(), causes))
+                },
+            };
+            let diags = match dir {
+                Some(ref dir) => bagger.check_manifests(dir),
+                None => Vec::new(),
+            };
+            if env::var_os("BAGGER_PRINT_CONFIG").is_some() {
+                eprintln!("{}", bagger.config());
+            }
+            *shared = Some(Shared {
+                dir,
+                watched,
+                stamps: loaded_stamps,
+                bagger,
+                profiles,
+                diags,
+                warned: Cell::new(false),
+            });
+        }
+
+        Ok(func(shared.as_ref().unwrap()))
+    })
+}
+
+/// Write the search graph of a request to `<dir>/<path>-<hash>.dot` and
+/// `<dir>/<path>-<hash>.json`. The hash of the path keeps apart paths that
+/// differ only in punctuation.
+fn dump_graph(dir: &Path, path: &str, graph: &SearchGraph) -> io::Result<()> {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    let name: String = path.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    let name = format!("{}-{:08x}", name, hasher.finish() as u32);
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(format!("{}.dot", name)), graph.to_dot())?;
+    let json = graph.to_json()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    fs::write(dir.join(format!("{}.json", name)), json)
+}
+
+/// Items that make the compiler warn with the given messages. Proc macros
+/// can not emit warnings themselves, and lints such as `deprecated` are not
+/// reported inside derives, so each message is made the note of a `must_use`
+/// function whose result is then ignored.
+fn warnings(messages: &[String]) -> quote::Tokens {
+    quote! {
+        #(
+            const _: () = {
+                #[must_use = #messages]
+                fn bagger_warning() -> u8 { 0 }
+                #[allow(dead_code)]
+                fn warn() { bagger_warning(); }
+            };
+        )*
+    }
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    let expanded = quote! { compile_error!(#msg); };
+    expanded.into()
+}
 
 #[proc_macro_derive(InitBag, attributes(bagger))]
 pub fn derive_init_try_bag(input: TokenStream) -> TokenStream {
@@ -99,7 +277,8 @@ pub fn derive_init_try_bag(input: TokenStream) -> TokenStream {
     }
 
     struct Metadata {
-        pub uri: Option<Uri>,
+        /// The URI, not yet interpolated or parsed.
+        pub uri: Option<String>,
         pub target: BagInfo,
         pub require: FlagSet,
         pub forbid: FlagSet,
@@ -111,10 +290,10 @@ pub fn derive_init_try_bag(input: TokenStream) -> TokenStream {
             let name = nv.ident.as_ref().to_owned(); 
 
             match name.as_str() {
-                "uri" => self.uri = Some(Uri::from_str(&match nv.lit {
+                "uri" => self.uri = Some(match nv.lit {
                     syn::Lit::Str(ref s) => s.value(),
                     _ => panic!("literal is not a string"),
-                }).expect("URI is not valid")),
+                }),
                 k => panic!("unknown bagger key \"{}\"", k),
             }
         }
@@ -157,29 +336,136 @@ pub fn derive_init_try_bag(input: TokenStream) -> TokenStream {
     visit::visit_data(&mut meta, &input.data);
     meta.target.simplify();
 
+    // interpolate the URI before parsing it, so that variables can name the
+    // scheme. Only the arguments are left to interpolate afterwards
+    let mut env = BTreeSet::new();
+    let uri = match interpolate(&meta.uri.expect("URI not provided"), &mut env) {
+        Ok(uri) => uri,
+        Err(e) => return compile_error(&e.to_string()),
+    };
+    let uri = match Uri::from_str(&uri) {
+        Ok(uri) => uri,
+        Err(_) => return compile_error(&format!("URI \"{}\" is not valid", uri)),
+    };
+    let mut args = meta.args;
+    for val in args.values_mut() {
+        *val = match interpolate(val, &mut env) {
+            Ok(val) => val,
+            Err(e) => return compile_error(&e.to_string()),
+        };
+    }
     let req = BagRequest {
-        uri: meta.uri.expect("URI not provided"),
+        uri,
         target: meta.target,
         required: meta.require,
         forbidden: meta.forbid,
-        args: meta.args,
+        args,
         span: Span::call_site(),
+        env,
+        runtime_path: None,
     };
+    let env_vars: Vec<_> = req.env.iter().cloned().collect();
 
-    let bggr = Bagger::new();
-    let sol = bggr.solve(req).expect("could not bag");
+    // report manifest problems before they surface as confusing solver errors,
+    // then solve, reusing earlier solutions to the same request. If the
+    // profile matters but is unknown, solve for both and let the expanding
+    // crate pick by `debug_assertions`
+    let path = req.uri.path.display().to_string();
+    let solved = with_bagger(|shared| {
+        let diags = shared.bagger.check_request(&req);
+        // manifest warnings are reported once, by the first expansion
+        let mut warned: Vec<_> = diags.iter()
+            .filter(|d| !d.is_error())
+            .map(ToString::to_string)
+            .collect();
+        if !shared.warned.replace(true) {
+            warned.extend(shared.diags.iter()
+                .filter(|d| !d.is_error())
+                .map(ToString::to_string));
+        }
+        let errors: Vec<_> = diags.iter()
+            .chain(&shared.diags)
+            .filter(|d| d.is_error())
+            .map(ToString::to_string)
+            .collect();
+        if !errors.is_empty() {
+            return Err(errors.join("\n"));
+        }
+        let manifests: Vec<_> = shared.bagger.config().sources.iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        let baggers = match shared.profiles {
+            Some((ref dev, ref release)) => vec![(Some("dev"), dev), (Some("release"), release)],
+            None => vec![(None, &shared.bagger)],
+        };
+        // and report arguments the route taken does not use
+        let mut solved = Vec::new();
+        for (profile, bagger) in baggers {
+            let (sol, graph) = bagger.solve_cached(req.clone());
+            if let Ok(ref sol) = sol {
+                for diag in bagger.check_solution(&req, sol) {
+                    let message = diag.to_string();
+                    if !warned.contains(&message) { warned.push(message) }
+                }
+            }
+            solved.push((profile, (sol, graph)));
+        }
+        Ok((solved, manifests, warned))
+    });
+    let (solved, manifests, warned) = match solved {
+        Ok(Ok(solved)) => solved,
+        Ok(Err(errors)) => return compile_error(&errors),
+        Err(diags) => {
+            let errors: Vec<_> = diags.iter().map(ToString::to_string).collect();
+            return compile_error(&errors.join("\n"))
+        },
+    };
 
     let ident = input.ident;
-    let bag_type = sol.bag_expr.returns;
-    let bag_expr = sol.bag_expr.expr;
+    let mut impls = Vec::new();
+    for (profile, (sol, graph)) in solved {
+        let name = match profile {
+            Some(profile) => format!("{} ({})", path, profile),
+            None => path.clone(),
+        };
+        if let Some(dir) = env::var_os("BAGGER_DUMP_GRAPH") {
+            if let Err(e) = dump_graph(Path::new(&dir), &name, &graph) {
+                eprintln!("could not dump search graph for \"{}\": {}", name, e);
+            }
+        }
+        let sol = match sol {
+            Ok(sol) => sol,
+            Err(e) => return compile_error(&format!("could not bag \"{}\": {}", name, e)),
+        };
+        if env::var_os("BAGGER_PRINT_SOLUTION").is_some() {
+            eprintln!("bagged \"{}\": {}", name, sol.describe());
+        }
+
+        let cfg = match profile {
+            Some("dev") => Some(quote! { #[cfg(debug_assertions)] }),
+            Some(_) => Some(quote! { #[cfg(not(debug_assertions))] }),
+            None => None,
+        };
+        let bag_type = sol.bag_expr.returns;
+        let bag_expr = sol.bag_expr.expr;
+        impls.push(quote! {
+            #cfg
+            #[allow(deprecated)]
+            impl ::bag::InitBag for #ident {
+                type Bag = #bag_type;
+                fn init() -> Self::Bag { #bag_expr }
+            }
+        });
+    }
 
+    let warnings = warnings(&warned);
     let expanded = quote! {
-        #[allow(deprecated)]
-        impl ::bag::InitBag for #ident {
-            type Bag = #bag_type;
-            fn init() -> Self::Bag { #bag_expr }
-        }
+        // rebuild when the environment or the manifests change. Unused
+        // consts are not compiled into the binary
+        #(const _: &str = env!(#env_vars);)*
+        #(const _: &[u8] = include_bytes!(#manifests);)*
+        #warnings
+        #(#impls)*
     };
-
     expanded.into()
 }
@@ -1,10 +1,14 @@
-use ::{Bagger, NodeInput, EdgeBuilder, Flag};
+use ::{Bagger, NodeInput, EdgeBuilder, EdgeKind, Flag, Cost};
+use config::Format;
 use nodes::*;
 use expr::*;
 
-use failure::err_msg;
+use failure::{Error, ResultExt, err_msg};
 use mime::Mime;
+use quote::Tokens;
+use syn::{self, Ident, Type, Path};
 
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::io;
 
@@ -27,12 +31,31 @@ fn get_mime(node: &NodeInput<LocalPath>) -> Mime {
     guess_mime_type(&node.node.0)
 }
 
+/// How a bag loads its file at run time.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum RuntimeLoad {
+    /// When first borrowed.
+    Blocking,
+    /// On its own thread, when first awaited.
+    Async,
+    /// When first borrowed, and again whenever the file changes.
+    Reload,
+}
+
 pub fn register_builtins(bggr: &mut Bagger) {
     let static_flag = Flag::from_str("static");
     let include_flag = Flag::from_str("include");
+    let reload_flag = Flag::from_str("reload");
+    bggr.declare_flag("static");
+    bggr.declare_flag("include");
+    bggr.declare_flag("reload");
+    bggr.declare_arg("content");
+    bggr.declare_route_arg("retry");
+    bggr.declare_arg("retry_backoff_ms");
 
     // Request -> LocalPath
-    bggr.transform(|mut n: NodeInput<Request>| {
+    let edges = vec![EdgeKind::to::<LocalPath>(&[])];
+    bggr.transform_declared(edges, |mut n: NodeInput<Request>| {
         let uri = &n.node.0;
         let path = uri.path.clone();
         let mut edge = EdgeBuilder::new();
@@ -48,11 +71,13 @@ pub fn register_builtins(bggr: &mut Bagger) {
     });
 
     // LocalPath -> LocalRead
-    bggr.transform(|mut n: NodeInput<LocalPath>| {
+    let edges = vec![EdgeKind::to::<LocalRead>(&[])];
+    bggr.transform_declared(edges, |mut n: NodeInput<LocalPath>| {
         use std::fs::File;
 
         // build edge
         let mut edge = EdgeBuilder::new();
+        edge.cost(Cost::new(1, 0, 0));
 
         // read file
         let path = n.node.0.clone();
@@ -67,21 +92,26 @@ pub fn register_builtins(bggr: &mut Bagger) {
 
     // LocalPath -> Producer<[u8]>, Producer<str>
     // uses include_*!
-    bggr.transform(move |mut n: NodeInput<LocalPath>| {
+    let edges = vec![
+        EdgeKind::to::<Producer>(&["static", "include"]),
+        EdgeKind::to::<Data>(&["static", "include"]),
+    ];
+    bggr.transform_declared(edges, move |mut n: NodeInput<LocalPath>| {
         let flags = &[static_flag, include_flag];
         let span = n.span;
 
         let bytes_expr_type = ExprType::of(parse_quote!(&'static [u8]));
         let bytes_info = BagInfo::from_quote(parse_quote!(
-            Bag<[u8]> + Unbag<&'static [u8]> + Unbag<Vec<u8>>
+            Bag<[u8]> + AsyncBag<[u8]> + Unbag<&'static [u8]> + Unbag<Vec<u8>>
         )).unwrap();
 
         let mut bytes_edge = EdgeBuilder::new();
         bytes_edge.satisfies_flags(flags);
+        bytes_edge.cost(Cost::new(1, 1, 0));
 
         let str_expr_type = ExprType::of(parse_quote!(&'static str));
         let str_info = BagInfo::from_quote(parse_quote!(
-            Bag<str> + Unbag<&'static str> + Unbag<String>
+            Bag<str> + AsyncBag<str> + Unbag<&'static str> + Unbag<String>
         )).unwrap();
 
         let mut str_edge = EdgeBuilder::new();
@@ -89,6 +119,11 @@ pub fn register_builtins(bggr: &mut Bagger) {
             str_edge.stop(err_msg("file content is not text"));
         }
         str_edge.satisfies_flags(flags);
+        str_edge.cost(Cost::new(1, 1, 0));
+
+        let mut data_edge = EdgeBuilder::new();
+        data_edge.satisfies_flags(flags);
+        data_edge.cost(Cost::new(1, 1, 0));
 
         if let Some(path) = n.node.0.to_str().map(ToOwned::to_owned) {
             let bytes_path = path.clone();
@@ -97,6 +132,12 @@ pub fn register_builtins(bggr: &mut Bagger) {
                 bytes_expr_type.clone(),
             ).bag_static()));
 
+            let data_path = path.clone();
+            data_edge.value(move |_| Ok(Expr::from_quote(
+                quote_spanned! { span => include_bytes!(#data_path) },
+                ExprType::of(parse_quote!(&'static [u8])),
+            )));
+
             str_edge.value(move |_| Ok(Expr::from_quote(
                 quote_spanned! { span => include_str!(#path) },
                 str_expr_type.clone(),
@@ -104,14 +145,147 @@ pub fn register_builtins(bggr: &mut Bagger) {
         } else {
             bytes_edge.stop(err_msg("path not utf-8"));
             str_edge.stop(err_msg("path not utf-8"));
+            data_edge.stop(err_msg("path not utf-8"));
         }
 
         n.edges.add(Producer(bytes_info), bytes_edge);
         n.edges.add(Producer(str_info), str_edge);
+        n.edges.add(Data(ExprType::of(parse_quote!(&'static [u8]))), data_edge);
+    });
+
+    // LocalPath -> Producer<[u8]>, Producer<str>
+    // loads the file at run time, either when first borrowed or on its own
+    // thread when first awaited, or whenever it changes if reload is required
+    let edges = vec![EdgeKind::to::<Producer>(&["reload"])];
+    bggr.transform_declared(edges, move |mut n: NodeInput<LocalPath>| {
+        let span = n.span;
+        let text = is_text(&get_mime(&n));
+
+        let logical = n.uri.path.to_str().map(ToOwned::to_owned);
+        let baked = match n.runtime_path {
+            Some(p) => Some(p.to_owned()),
+            None => n.node.0.to_str().map(ToOwned::to_owned),
+        };
+        // blocking loads try again after failures if given a number of attempts
+        let retry = n.arg("retry").map(|attempts| retry_policy(attempts, n.arg("retry_backoff_ms")));
+
+        let mut producers: Vec<(BagInfo, Type, RuntimeLoad, bool)> = vec![
+            (
+                BagInfo::from_quote(parse_quote!(TryBag<[u8]> + TryUnbag<Vec<u8>>)).unwrap(),
+                parse_quote!(Vec<u8>),
+                RuntimeLoad::Blocking,
+                false,
+            ),
+            (
+                BagInfo::from_quote(parse_quote!(TryBag<str> + TryUnbag<String>)).unwrap(),
+                parse_quote!(String),
+                RuntimeLoad::Blocking,
+                true,
+            ),
+            (
+                BagInfo::from_quote(parse_quote!(AsyncBag<[u8]>)).unwrap(),
+                parse_quote!(Vec<u8>),
+                RuntimeLoad::Async,
+                false,
+            ),
+            (
+                BagInfo::from_quote(parse_quote!(AsyncBag<str>)).unwrap(),
+                parse_quote!(String),
+                RuntimeLoad::Async,
+                true,
+            ),
+        ];
+        // reloading is only for development, so never a fallback
+        if n.requires("reload") {
+            producers.push((
+                BagInfo::from_quote(parse_quote!(TryBag<[u8]> + TryUnbag<Vec<u8>>)).unwrap(),
+                parse_quote!(Vec<u8>),
+                RuntimeLoad::Reload,
+                false,
+            ));
+            producers.push((
+                BagInfo::from_quote(parse_quote!(TryBag<str> + TryUnbag<String>)).unwrap(),
+                parse_quote!(String),
+                RuntimeLoad::Reload,
+                true,
+            ));
+        }
+
+        for (info, ty, load, needs_text) in producers {
+            let mut edge = EdgeBuilder::new();
+            edge.cost(Cost::new(0, 0, 8));
+            if load == RuntimeLoad::Reload {
+                edge.satisfies_flag(reload_flag);
+            }
+            if needs_text && !text {
+                edge.stop(err_msg("file content is not text"));
+            }
+
+            let (load, policy, returns): (Tokens, Option<Tokens>, Type) = match (load, &retry) {
+                (_, &Some(Err(ref e))) => {
+                    edge.stop(format_err!("{}", e));
+                    n.edges.add(Producer(info), edge);
+                    continue
+                },
+                (RuntimeLoad::Async, &Some(Ok(_))) => {
+                    edge.stop(err_msg("retry is not supported by bags loaded asynchronously"));
+                    n.edges.add(Producer(info), edge);
+                    continue
+                },
+                (RuntimeLoad::Reload, &Some(Ok(_))) => {
+                    edge.stop(err_msg("retry is not supported by reloading bags"));
+                    n.edges.add(Producer(info), edge);
+                    continue
+                },
+                (RuntimeLoad::Async, &None) => (
+                    quote!(::bag::ops::async_runtime_file::<#ty>),
+                    None,
+                    parse_quote!(::bag::ops::AsyncRuntimeFile<#ty>),
+                ),
+                (RuntimeLoad::Reload, &None) => (
+                    quote!(::bag::ops::reloading_runtime_file::<#ty>),
+                    None,
+                    parse_quote!(::bag::ops::ReloadingRuntimeFile<#ty>),
+                ),
+                (RuntimeLoad::Blocking, &Some(Ok(ref policy))) => {
+                    edge.uses_arg("retry");
+                    (
+                        quote!(::bag::ops::retrying_runtime_file::<#ty>),
+                        Some(policy.clone()),
+                        parse_quote!(::bag::ops::RetryingRuntimeFile<#ty>),
+                    )
+                },
+                (RuntimeLoad::Blocking, &None) => (
+                    quote!(::bag::ops::runtime_file::<#ty>),
+                    None,
+                    parse_quote!(::bag::ops::RuntimeFile<#ty>),
+                ),
+            };
+
+            match (logical.clone(), baked.clone()) {
+                (Some(logical), Some(baked)) => edge.value(move |_| {
+                    let path = quote_spanned! { span =>
+                        ::bag::alias::RuntimePath::new(#logical, #baked)
+                    };
+                    let expr = match policy {
+                        Some(ref policy) => quote_spanned! { span => #load(#path, #policy) },
+                        None => quote_spanned! { span => #load(#path) },
+                    };
+                    Ok(BagExpr { expr, returns: returns.clone() })
+                }),
+                _ => edge.stop(err_msg("path not utf-8")),
+            }
+
+            n.edges.add(Producer(info), edge);
+        }
     });
 
     // LocalRead -> Producer<[u8]>, Producer<str>
-    bggr.transform(move |mut n: NodeInput<LocalRead>| {
+    let edges = vec![
+        EdgeKind::to::<Producer>(&["static"]),
+        EdgeKind::to::<Data>(&["static"]),
+    ];
+    bggr.transform_declared(edges, move |mut n: NodeInput<LocalRead>| {
         use syn::LitByteStr;
 
         let flags = &[static_flag];
@@ -119,12 +293,13 @@ pub fn register_builtins(bggr: &mut Bagger) {
 
         let bytes_expr_type = ExprType::of(parse_quote!(&'static [u8]));
         let bytes_info = BagInfo::from_quote(parse_quote!(
-            Bag<[u8]> + Unbag<&'static [u8]> + Unbag<Vec<u8>>
+            Bag<[u8]> + AsyncBag<[u8]> + Unbag<&'static [u8]> + Unbag<Vec<u8>>
         )).unwrap();
 
         // include byte string
         let mut edge = EdgeBuilder::new();
         edge.satisfies_flags(flags);
+        edge.cost(Cost::new(2, 1, 0));
         edge.value(move |mut read: Box<io::Read>| {
             let mut bytes = Vec::new();
             read.read_to_end(&mut bytes)?;
@@ -135,13 +310,28 @@ pub fn register_builtins(bggr: &mut Bagger) {
         });
         n.edges.add(Producer(bytes_info), edge);
 
+        // byte string for further decoding
+        let mut edge = EdgeBuilder::new();
+        edge.satisfies_flags(flags);
+        edge.cost(Cost::new(2, 1, 0));
+        edge.value(move |mut read: Box<io::Read>| {
+            let mut bytes = Vec::new();
+            read.read_to_end(&mut bytes)?;
+            Ok(Expr::from_quote(
+                LitByteStr::new(&bytes, span),
+                ExprType::of(parse_quote!(&'static [u8])),
+            ))
+        });
+        n.edges.add(Data(ExprType::of(parse_quote!(&'static [u8]))), edge);
+
         let str_expr_type = ExprType::of(parse_quote!(&'static str));
         let str_info = BagInfo::from_quote(parse_quote!(
-            Bag<str> + Unbag<&'static str> + Unbag<String>
+            Bag<str> + AsyncBag<str> + Unbag<&'static str> + Unbag<String>
         )).unwrap();
 
         let mut edge = EdgeBuilder::new();
         edge.satisfies_flags(flags);
+        edge.cost(Cost::new(2, 1, 0));
         edge.value(move |mut read: Box<io::Read>| {
             let mut string = String::new();
             read.read_to_string(&mut string)?;
@@ -156,3 +346,71 @@ pub fn register_builtins(bggr: &mut Bagger) {
         n.edges.add(Producer(str_info), edge);
     });
 }
+
+/// Register a `Data<&'static [u8]> -> Producer<T>` transform for each
+/// `[format."T"]`, which runs the loader on the data at run time.
+pub fn register_formats(
+    bggr: &mut Bagger,
+    formats: &BTreeMap<String, Format>,
+)
+    -> Result<(), Error>
+{
+    for (ty_text, format) in formats {
+        syn::parse_str::<Type>(ty_text)
+            .map_err(|_| format_err!("format \"{}\" is not a type", ty_text))?;
+        syn::parse_str::<Path>(&format.loader)
+            .map_err(|_| format_err!("loader \"{}\" is not a path", format.loader))
+            .with_context(|_| format!("invalid format \"{}\"", ty_text))?;
+
+        // syntax trees are not Send, so reparse on every use
+        let ty_text = ty_text.clone();
+        let loader_text = format.loader.clone();
+        let edges = vec![EdgeKind::to::<Producer>(&[])];
+        bggr.transform_declared(edges, move |mut n: NodeInput<Data>| {
+            let span = n.span;
+            let bytes_type: Type = parse_quote!(&'static [u8]);
+            if n.node.0 != ExprType::of(bytes_type) { return }
+
+            let ty: Type = syn::parse_str(&ty_text).unwrap();
+            let info = BagInfo::simple_try(ty.clone(), Some(ty));
+
+            let ty_text = ty_text.clone();
+            let loader_text = loader_text.clone();
+            let mut edge = EdgeBuilder::new();
+            edge.cost(Cost::new(0, 0, 1));
+            edge.value(move |bytes: Expr| {
+                let ty: Type = syn::parse_str(&ty_text).unwrap();
+                let loader: Path = syn::parse_str(&loader_text).unwrap();
+                let input = Ident::from("bytes");
+                Ok(Expr {
+                    inputs: vec![(input, bytes)],
+                    expr: quote_spanned! { span =>
+                        <#loader as ::bag::ops::Loader<#ty>>::load(#input)
+                    },
+                    returns: ExprType::of_result(ty),
+                }.bag_lazy_map())
+            });
+            n.edges.add(Producer(info), edge);
+        });
+    }
+    Ok(())
+}
+
+/// The expression for a `RetryPolicy` from the `retry` and `retry_backoff_ms`
+/// arguments.
+fn retry_policy(attempts: &str, backoff_ms: Option<&str>) -> Result<Tokens, Error> {
+    let attempts = usize::from_str(attempts)
+        .map_err(|_| format_err!("retry \"{}\" is not a number of attempts", attempts))?;
+    let backoff_ms = match backoff_ms {
+        Some(ms) => u64::from_str(ms)
+            .map_err(|_| format_err!("retry_backoff_ms \"{}\" is not a number", ms))?,
+        None => 100,
+    };
+    Ok(quote! {
+        ::bag::bags::RetryPolicy {
+            max_attempts: #attempts,
+            backoff: ::std::time::Duration::from_millis(#backoff_ms),
+            ..::bag::bags::RetryPolicy::default()
+        }
+    })
+}
@@ -0,0 +1,140 @@
+//! Solutions remembered between requests, so that a crate bagging the same
+//! asset many times solves it once.
+//!
+//! Solutions are kept as source text, since tokens can not outlive the macro
+//! expansion that made them. A cached solution is parsed again with the spans
+//! of the expansion using it, so any spans the transforms gave it are lost.
+//!
+//! Relative paths are stamped against the root of the crate being built, not
+//! the current directory.
+
+use ::{BagRequest, Flag, Uri};
+use expr::BagExpr;
+use report::SearchGraph;
+use solver::{Solution, Step};
+
+use failure::Error;
+use proc_macro2::TokenStream;
+use syn::Type;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Modification time and length of a file, if it exists.
+type Stamp = Option<(SystemTime, u64)>;
+
+fn stamp(path: &Path) -> Stamp {
+    fs::metadata(path).ok()
+        .and_then(|m| m.modified().ok().map(|t| (t, m.len())))
+}
+
+/// Identifies a request, and the state of the file it starts from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    root: PathBuf,
+    scheme: Option<String>,
+    path: PathBuf,
+    target: String,
+    required: Vec<String>,
+    forbidden: Vec<String>,
+    args: Vec<(String, String)>,
+    runtime_path: Option<String>,
+    stamp: Stamp,
+}
+
+impl CacheKey {
+    /// The key of a request that is solved starting from the given URI, for
+    /// the crate in the given directory.
+    pub fn new(root: &Path, start: &Uri, bag: &BagRequest) -> CacheKey {
+        let mut required: Vec<_> = bag.required.iter().map(Flag::name).collect();
+        required.sort();
+        let mut forbidden: Vec<_> = bag.forbidden.iter().map(Flag::name).collect();
+        forbidden.sort();
+        let mut args: Vec<_> = bag.args.iter()
+            .map(|(f, v)| (f.name(), v.clone()))
+            .collect();
+        args.sort();
+
+        CacheKey {
+            root: root.to_owned(),
+            scheme: start.scheme.clone(),
+            path: start.path.clone(),
+            target: bag.target.to_string(),
+            required,
+            forbidden,
+            args,
+            runtime_path: bag.runtime_path.clone(),
+            stamp: stamp(&root.join(&start.path)),
+        }
+    }
+}
+
+struct Entry {
+    expr: String,
+    returns: String,
+    path: Vec<Step>,
+    /// The requests joined by the solution, and the state of their files.
+    joined: Vec<(Uri, Stamp)>,
+    graph: SearchGraph,
+}
+
+/// Solutions to requests, with the searches that found them.
+#[derive(Default)]
+pub struct SolutionCache {
+    entries: HashMap<CacheKey, Entry>,
+}
+
+impl SolutionCache {
+    pub fn new() -> SolutionCache {
+        SolutionCache::default()
+    }
+
+    /// Get the solution to a request, if it was solved before and no file it
+    /// joins has changed since.
+    pub fn get(&self, key: &CacheKey) -> Result<Option<(Solution, SearchGraph)>, Error> {
+        let entry = match self.entries.get(key) {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        if entry.joined.iter().any(|&(ref uri, s)| stamp(&key.root.join(&uri.path)) != s) {
+            return Ok(None)
+        }
+        let expr: TokenStream = entry.expr.parse()
+            .map_err(|_| format_err!("cached solution does not parse"))?;
+        let returns: Type = ::syn::parse_str(&entry.returns)?;
+        let mut sol = Solution::new(BagExpr { expr: quote! { #expr }, returns });
+        sol.path = entry.path.clone();
+        sol.joined = entry.joined.iter().map(|&(ref uri, _)| uri.clone()).collect();
+        Ok(Some((sol, entry.graph.clone())))
+    }
+
+    /// Remember the solution to a request.
+    pub fn insert(&mut self, key: CacheKey, sol: &Solution, graph: &SearchGraph) {
+        let returns = &sol.bag_expr.returns;
+        let joined = sol.joined.iter()
+            .map(|uri| (uri.clone(), stamp(&key.root.join(&uri.path))))
+            .collect();
+        self.entries.insert(key, Entry {
+            expr: sol.bag_expr.expr.to_string(),
+            returns: quote!(#returns).to_string(),
+            path: sol.path.clone(),
+            joined,
+            graph: graph.clone(),
+        });
+    }
+
+    /// Number of solutions remembered.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+}
@@ -0,0 +1,280 @@
+//! Evaluation of `[target."cfg(...)"]` keys against the crate being built.
+//!
+//! Cargo only exposes the target's cfg options to build scripts, as
+//! `TARGET` and `CARGO_CFG_*`. A build script can forward them to macro
+//! expansion with `cargo:rustc-env=TARGET=...`. If only `TARGET` is known,
+//! the common options are guessed from the target triple.
+
+use failure::Error;
+
+use std::collections::BTreeSet;
+use std::env;
+use std::iter::Peekable;
+use std::str::{Chars, FromStr};
+
+/// A target key: either `cfg(...)` or a target triple.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cfg {
+    Triple(String),
+    Name(String),
+    KeyValue(String, String),
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Open,
+    Close,
+    Comma,
+    Equals,
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Result<Token, Error>> {
+        while self.chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            self.chars.next();
+        }
+
+        let c = self.chars.next()?;
+        Some(Ok(match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '=' => Token::Equals,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match self.chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Some(Err(format_err!("unterminated string in cfg"))),
+                    }
+                }
+                Token::Str(s)
+            },
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = c.to_string();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') { break }
+                    s.push(c);
+                    self.chars.next();
+                }
+                Token::Ident(s)
+            },
+            c => return Some(Err(format_err!("unexpected character '{}' in cfg", c))),
+        }))
+    }
+}
+
+struct Parser<'a> {
+    tokens: Peekable<Lexer<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<Token, Error> {
+        match self.tokens.next() {
+            Some(t) => t,
+            None => bail!("unexpected end of cfg"),
+        }
+    }
+
+    fn expect(&mut self, tok: Token) -> Result<(), Error> {
+        let next = self.next()?;
+        if next != tok { bail!("expected {:?} in cfg, found {:?}", tok, next) }
+        Ok(())
+    }
+
+    fn list(&mut self) -> Result<Vec<Cfg>, Error> {
+        self.expect(Token::Open)?;
+        let mut list = Vec::new();
+        loop {
+            if let Some(&Ok(Token::Close)) = self.tokens.peek() {
+                self.next()?;
+                return Ok(list)
+            }
+            list.push(self.cfg()?);
+            match self.next()? {
+                Token::Comma => (),
+                Token::Close => return Ok(list),
+                t => bail!("expected ',' or ')' in cfg, found {:?}", t),
+            }
+        }
+    }
+
+    fn cfg(&mut self) -> Result<Cfg, Error> {
+        let name = match self.next()? {
+            Token::Ident(name) => name,
+            t => bail!("expected identifier in cfg, found {:?}", t),
+        };
+
+        Ok(match name.as_str() {
+            "all" => Cfg::All(self.list()?),
+            "any" => Cfg::Any(self.list()?),
+            "not" => {
+                let mut list = self.list()?;
+                if list.len() != 1 { bail!("not() takes exactly one predicate") }
+                Cfg::Not(Box::new(list.pop().unwrap()))
+            },
+            _ => if let Some(&Ok(Token::Equals)) = self.tokens.peek() {
+                self.next()?;
+                match self.next()? {
+                    Token::Str(val) => Cfg::KeyValue(name, val),
+                    t => bail!("expected string in cfg, found {:?}", t),
+                }
+            } else {
+                Cfg::Name(name)
+            },
+        })
+    }
+}
+
+impl FromStr for Cfg {
+    type Err = Error;
+
+    fn from_str(key: &str) -> Result<Cfg, Error> {
+        let key = key.trim();
+        if !key.starts_with("cfg(") {
+            return Ok(Cfg::Triple(key.to_owned()))
+        }
+
+        let mut parser = Parser {
+            tokens: Lexer { chars: key["cfg".len()..].chars().peekable() }.peekable(),
+        };
+        let mut list = parser.list()?;
+        if parser.tokens.next().is_some() { bail!("trailing tokens after cfg") }
+        if list.len() != 1 { bail!("cfg() takes exactly one predicate") }
+        Ok(list.pop().unwrap())
+    }
+}
+
+impl Cfg {
+    /// Is this predicate true for the given target?
+    pub fn eval(&self, set: &CfgSet) -> bool {
+        match *self {
+            Cfg::Triple(ref t) => set.triple.as_ref() == Some(t),
+            Cfg::Name(ref n) => set.names.contains(n),
+            Cfg::KeyValue(ref k, ref v) => set.values.contains(&(k.clone(), v.clone())),
+            Cfg::All(ref l) => l.iter().all(|c| c.eval(set)),
+            Cfg::Any(ref l) => l.iter().any(|c| c.eval(set)),
+            Cfg::Not(ref c) => !c.eval(set),
+        }
+    }
+}
+
+/// The cfg options of a target.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    pub triple: Option<String>,
+    pub names: BTreeSet<String>,
+    pub values: BTreeSet<(String, String)>,
+}
+
+impl CfgSet {
+    /// An empty set, where only `not(...)` predicates hold.
+    pub fn new() -> CfgSet {
+        CfgSet::default()
+    }
+
+    /// Read the target from `TARGET` and `CARGO_CFG_*`, if either is set.
+    ///
+    /// Cargo only sets these for build scripts, so during macro expansion the
+    /// target is unknown unless a build script forwards it, such as with
+    /// `cargo:rustc-env=TARGET=...`.
+    pub fn from_env() -> Option<CfgSet> {
+        let triple = env::var("TARGET").ok();
+        let mut set = match triple {
+            Some(ref triple) => CfgSet::from_triple(triple),
+            None => CfgSet::new(),
+        };
+
+        let mut cargo_cfg = CfgSet::new();
+        for (key, val) in env::vars() {
+            if !key.starts_with("CARGO_CFG_") { continue }
+            let key = key["CARGO_CFG_".len()..].to_lowercase();
+            if val.is_empty() {
+                cargo_cfg.names.insert(key);
+            } else {
+                for val in val.split(',') {
+                    cargo_cfg.values.insert((key.clone(), val.to_owned()));
+                }
+            }
+        }
+
+        // cargo knows better than our guesses
+        if !cargo_cfg.names.is_empty() || !cargo_cfg.values.is_empty() {
+            set.names = cargo_cfg.names;
+            set.values = cargo_cfg.values;
+        } else if triple.is_none() {
+            return None
+        }
+        Some(set)
+    }
+
+    /// Guess the common cfg options of a target triple.
+    pub fn from_triple(triple: &str) -> CfgSet {
+        let mut set = CfgSet::new();
+        set.triple = Some(triple.to_owned());
+
+        let parts: Vec<_> = triple.split('-').collect();
+        let arch = match parts[0] {
+            "i386" | "i586" | "i686" => "x86",
+            a if a.starts_with("armv") || a.starts_with("thumbv") => "arm",
+            a => a,
+        };
+        set.insert("target_arch", arch);
+
+        let has = |name: &str| parts[1..].iter().any(|&p| p == name);
+        let os = if has("linux") {
+            "linux"
+        } else if has("windows") {
+            "windows"
+        } else if has("darwin") || has("macos") {
+            "macos"
+        } else {
+            ["android", "ios", "freebsd", "netbsd", "openbsd", "dragonfly",
+                "emscripten", "wasi", "redox", "fuchsia"]
+                .iter()
+                .cloned()
+                .find(|&os| has(os) || parts.iter().any(|p| p.starts_with(os)))
+                .unwrap_or("unknown")
+        };
+        set.insert("target_os", os);
+
+        if parts.len() > 2 {
+            set.insert("target_vendor", parts[1]);
+        }
+        if let Some(env) = parts.last().and_then(|p| ["gnu", "musl", "msvc"]
+            .iter()
+            .cloned()
+            .find(|e| p.starts_with(e)))
+        {
+            set.insert("target_env", env);
+        }
+
+        if arch.starts_with("wasm") {
+            set.insert("target_family", "wasm");
+        } else if os == "windows" {
+            set.insert("target_family", "windows");
+            set.names.insert("windows".to_owned());
+        } else if os != "unknown" {
+            set.insert("target_family", "unix");
+            set.names.insert("unix".to_owned());
+        }
+
+        set
+    }
+
+    fn insert(&mut self, key: &str, val: &str) {
+        self.values.insert((key.to_owned(), val.to_owned()));
+    }
+}
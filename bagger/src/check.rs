@@ -0,0 +1,463 @@
+//! Validation of `Bag.toml` manifests and requests.
+
+use ::{BagRequest, Flag, Solution};
+use flag::FlagSet;
+use cfg::Cfg;
+use config::{self, Config};
+use bag::alias::{AliasTable, Glob};
+
+use syn::{self, Path as SynPath, Type};
+use toml::Value;
+
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The manifest is not valid TOML.
+    Syntax,
+    /// A key that bagger does not use.
+    UnknownKey,
+    /// A value of the wrong type, or a key that can not be parsed.
+    InvalidValue,
+    /// A flag that no transform satisfies.
+    UnknownFlag,
+    /// An argument that no transform reads.
+    UnknownArg,
+    /// An alias that matches no files.
+    UnmatchedAlias,
+    /// A format loader that is not a path.
+    InvalidLoader,
+    /// A profile other than `dev` or `release`, which only applies if a
+    /// build script forwards it.
+    UnknownProfile,
+    /// An argument that the route taken by a solution does not use.
+    IgnoredArg,
+}
+
+/// A problem with a manifest or request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    /// One-based line number.
+    pub line: Option<usize>,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(ref file) = self.file {
+            write!(f, "{}:", file.display())?;
+            if let Some(line) = self.line {
+                write!(f, "{}:", line)?;
+            }
+            write!(f, " ")?;
+        }
+        let level = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", level, self.message)
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| !(c.is_whitespace() || c == '"' || c == '\'' || c == '\\'))
+        .collect()
+}
+
+fn read_text(path: &Path) -> Option<String> {
+    let mut text = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut text)).ok()?;
+    Some(text)
+}
+
+fn is_header(line: &str) -> bool {
+    line.trim().starts_with('[')
+}
+
+/// Index of the first line after the header of a table.
+fn find_table(lines: &[&str], table: &[&str]) -> Option<usize> {
+    if table.is_empty() { return Some(0) }
+    let header = format!("[{}]", normalize(&table.join(".")));
+    let double = format!("[{}]", header);
+    lines.iter()
+        .position(|l| {
+            let l = normalize(l);
+            l == header || l == double
+        })
+        .map(|i| i + 1)
+}
+
+/// Index of the line defining a key, in the table starting at `start`.
+fn find_key(lines: &[&str], start: usize, key: &str) -> Option<usize> {
+    let key = normalize(key);
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        if is_header(line) { break }
+        let mut split = line.splitn(2, '=');
+        let lhs = split.next().unwrap_or("");
+        if split.next().is_some() && normalize(lhs) == key {
+            return Some(i)
+        }
+    }
+    None
+}
+
+/// Find the one-based line defining a key path, such as `["target",
+/// "cfg(unix)", "static"]`. If the key itself can not be found, the closest
+/// enclosing table header is used.
+fn locate(text: &str, path: &[&str]) -> Option<usize> {
+    let lines: Vec<_> = text.lines().collect();
+    for split in (0..path.len() + 1).rev() {
+        let (table, keys) = path.split_at(split);
+        let start = match find_table(&lines, table) {
+            Some(s) => s,
+            None => continue,
+        };
+        if keys.is_empty() { return Some(start) }
+        if let Some(i) = find_key(&lines, start, keys[0]) {
+            return Some(i + 1)
+        }
+        if !table.is_empty() { return Some(start) }
+    }
+    None
+}
+
+/// Checks manifests against the flags, arguments, and files a bagger knows.
+pub struct Checker<'a> {
+    flags: &'a FlagSet,
+    args: &'a FlagSet,
+    diags: Vec<Diagnostic>,
+    file: Option<PathBuf>,
+    text: Option<String>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn new(flags: &'a FlagSet, args: &'a FlagSet) -> Checker<'a> {
+        Checker {
+            flags,
+            args,
+            diags: Vec::new(),
+            file: None,
+            text: None,
+        }
+    }
+
+    pub fn finish(self) -> Vec<Diagnostic> {
+        self.diags
+    }
+
+    fn report(&mut self, kind: DiagnosticKind, path: &[&str], message: String) {
+        // only problems that change what gets bagged are errors. A plugin may
+        // satisfy a flag it never declared, so unknown flags are only
+        // suspicious
+        let severity = match kind {
+            DiagnosticKind::UnknownFlag
+                | DiagnosticKind::UnknownArg
+                | DiagnosticKind::UnmatchedAlias
+                | DiagnosticKind::UnknownProfile
+                | DiagnosticKind::IgnoredArg => Severity::Warning,
+            _ => Severity::Error,
+        };
+        let line = self.text.as_ref().and_then(|t| locate(t, path));
+        self.diags.push(Diagnostic {
+            severity,
+            kind,
+            message,
+            file: self.file.clone(),
+            line,
+        });
+    }
+
+    /// Check a single manifest file.
+    pub fn check_file(&mut self, path: &Path) {
+        self.file = Some(path.to_owned());
+        self.text = None;
+
+        let text = match read_text(path) {
+            Some(t) => t,
+            None => {
+                self.report(DiagnosticKind::Syntax, &[], "could not read manifest".to_owned());
+                return
+            },
+        };
+        self.text = Some(text.clone());
+
+        match text.parse::<Value>() {
+            Ok(value) => self.check_value(&value),
+            Err(e) => self.report(DiagnosticKind::Syntax, &[], e.to_string()),
+        }
+    }
+
+    /// Check the contents of a manifest.
+    pub fn check_value(&mut self, value: &Value) {
+        let root = match *value {
+            Value::Table(ref t) => t,
+            _ => return,
+        };
+
+        for (key, val) in root {
+            match key.as_str() {
+                "defaults" => self.check_defaults(&["defaults"], val),
+                "alias" => self.check_aliases(&["alias"], val),
+                "runtime" => self.check_table(&["runtime"], val, |c, k, v| match k {
+                    "alias" => c.check_aliases(&["runtime", "alias"], v),
+                    k => c.unknown_key(&["runtime", k]),
+                }),
+                "target" => self.check_table(&["target"], val, |c, k, v| {
+                    if let Err(e) = Cfg::from_str(k) {
+                        c.report(
+                            DiagnosticKind::InvalidValue,
+                            &["target", k],
+                            format!("invalid target \"{}\": {}", k, e));
+                    }
+                    c.check_defaults(&["target", k], v);
+                }),
+                "profile" => self.check_table(&["profile"], val, |c, k, v| {
+                    match k {
+                        "dev" | "release" => (),
+                        k => c.report(
+                            DiagnosticKind::UnknownProfile,
+                            &["profile", k],
+                            format!(
+                                "unknown profile \"{}\" only applies if a build script \
+                                 forwards it as PROFILE", k)),
+                    }
+                    c.check_defaults(&["profile", k], v);
+                }),
+                "asset" => self.check_table(&["asset"], val, |c, k, v| {
+                    if let Err(e) = Glob::from_str(k) {
+                        c.report(
+                            DiagnosticKind::InvalidValue,
+                            &["asset", k],
+                            format!("invalid asset \"{}\": {}", k, e));
+                    }
+                    c.check_defaults(&["asset", k], v);
+                }),
+                "format" => self.check_table(&["format"], val, |c, k, v| {
+                    c.check_format(k, v)
+                }),
+                "search" => self.check_table(&["search"], val, |c, k, v| match (k, v) {
+                    ("max_expansions", &Value::Integer(n)) |
+                    ("time_limit_ms", &Value::Integer(n)) if n >= 0 => (),
+                    ("max_expansions", _) | ("time_limit_ms", _) => c.report(
+                        DiagnosticKind::InvalidValue,
+                        &["search", k],
+                        format!("\"search.{}\" must be a non-negative integer", k)),
+                    (k, _) => c.unknown_key(&["search", k]),
+                }),
+                k => self.unknown_key(&[k]),
+            }
+        }
+    }
+
+    fn unknown_key(&mut self, path: &[&str]) {
+        let message = format!("unknown key \"{}\"", path.join("."));
+        self.report(DiagnosticKind::UnknownKey, path, message);
+    }
+
+    fn check_table<F>(&mut self, path: &[&str], val: &Value, mut each: F)
+        where F: FnMut(&mut Self, &str, &Value)
+    {
+        match *val {
+            Value::Table(ref t) => for (k, v) in t { each(self, k, v) },
+            _ => self.report(
+                DiagnosticKind::InvalidValue,
+                path,
+                format!("\"{}\" must be a table", path.join("."))),
+        }
+    }
+
+    fn check_defaults(&mut self, path: &[&str], val: &Value) {
+        self.check_table(path, val, |c, k, v| {
+            let mut key = path.to_vec();
+            key.push(k);
+            let flag = Flag::from_str(k);
+            match *v {
+                Value::Boolean(_) => if !c.flags.contains(&flag) {
+                    c.report(DiagnosticKind::UnknownFlag, &key, format!("unknown flag \"{}\"", k))
+                },
+                Value::String(_) => if !c.args.contains(&flag) {
+                    c.report(DiagnosticKind::UnknownArg, &key, format!("unknown argument \"{}\"", k))
+                },
+                _ => c.report(
+                    DiagnosticKind::InvalidValue,
+                    &key,
+                    format!("\"{}\" must be a flag (true or false) or an argument (string)", k)),
+            }
+        })
+    }
+
+    fn check_aliases(&mut self, path: &[&str], val: &Value) {
+        self.check_table(path, val, |c, k, v| {
+            let mut key = path.to_vec();
+            key.push(k);
+            match *v {
+                Value::String(ref to) => if let Err(e) = AliasTable::new().insert(k, to) {
+                    c.report(DiagnosticKind::InvalidValue, &key, e.to_string())
+                },
+                _ => c.report(
+                    DiagnosticKind::InvalidValue,
+                    &key,
+                    format!("alias \"{}\" must be a string", k)),
+            }
+        })
+    }
+
+    fn check_format(&mut self, ty: &str, val: &Value) {
+        if syn::parse_str::<Type>(ty).is_err() {
+            self.report(
+                DiagnosticKind::InvalidValue,
+                &["format", ty],
+                format!("format \"{}\" is not a type", ty));
+        }
+
+        self.check_table(&["format", ty], val, |c, k, v| match (k, v) {
+            ("loader", &Value::String(ref loader)) =>
+                if syn::parse_str::<SynPath>(loader).is_err() {
+                    c.report(
+                        DiagnosticKind::InvalidLoader,
+                        &["format", ty, "loader"],
+                        format!("loader \"{}\" is not a path", loader))
+                },
+            ("loader", _) => c.report(
+                DiagnosticKind::InvalidLoader,
+                &["format", ty, "loader"],
+                "loader must be a string".to_owned()),
+            (k, _) => c.unknown_key(&["format", ty, k]),
+        });
+
+        let has_loader = match *val {
+            Value::Table(ref t) => t.contains_key("loader"),
+            _ => true,
+        };
+        if !has_loader {
+            self.report(
+                DiagnosticKind::InvalidLoader,
+                &["format", ty],
+                format!("format \"{}\" has no loader", ty));
+        }
+    }
+
+    /// Check that every compile-time alias matches some file in the crate.
+    pub fn check_alias_files(&mut self, config: &Config) {
+        if config.root.as_os_str().is_empty() { return }
+        let mut files = Vec::new();
+        list_files(&config.root, &config.root, &mut files);
+
+        for (from, to) in &config.alias {
+            let glob = match Glob::from_str(to) {
+                Ok(g) => g,
+                Err(_) => continue,
+            };
+            if files.iter().any(|f| glob.matches(f)) { continue }
+
+            // report in the innermost manifest that defines the alias
+            let source = config.sources.iter().rev()
+                .filter_map(|s| read_text(s).map(|t| (s, t)))
+                .find(|&(_, ref text)| {
+                    let lines: Vec<_> = text.lines().collect();
+                    find_table(&lines, &["alias"])
+                        .and_then(|start| find_key(&lines, start, from))
+                        .is_some()
+                });
+            let (file, text) = match source {
+                Some((f, t)) => (Some(f.clone()), Some(t)),
+                None => (None, None),
+            };
+            self.file = file;
+            self.text = text;
+            let message = format!("alias \"{}\" = \"{}\" matches no files", from, to);
+            self.report(DiagnosticKind::UnmatchedAlias, &["alias", from], message);
+        }
+    }
+
+    /// Check the flags and arguments of a request.
+    pub fn check_request(&mut self, req: &BagRequest) {
+        self.file = None;
+        self.text = None;
+
+        for flag in req.required.iter().chain(&req.forbidden) {
+            if !self.flags.contains(flag) {
+                let message = format!("unknown flag \"{}\"", flag);
+                self.report(DiagnosticKind::UnknownFlag, &[], message);
+            }
+        }
+        for arg in req.args.keys() {
+            if !self.args.contains(arg) {
+                let message = format!("unknown argument \"{}\"", arg);
+                self.report(DiagnosticKind::UnknownArg, &[], message);
+            }
+        }
+    }
+
+    /// Check that the route taken by a solution uses every argument given
+    /// to the request that only some routes use.
+    pub fn check_solution(&mut self, req: &BagRequest, sol: &Solution, route_args: &FlagSet) {
+        self.file = None;
+        self.text = None;
+
+        for arg in req.args.keys().filter(|a| route_args.contains(a)) {
+            let name = arg.name();
+            if !sol.path.iter().any(|s| s.args.contains(&name)) {
+                let message = format!(
+                    "argument \"{}\" is not supported by the route taken: {}",
+                    arg,
+                    sol.describe(),
+                );
+                self.report(DiagnosticKind::IgnoredArg, &[], message);
+            }
+        }
+    }
+}
+
+/// List files under a directory, relative to the crate root, skipping build
+/// output and hidden directories.
+fn list_files(root: &Path, dir: &Path, out: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if path.is_dir() {
+            if name.starts_with('.') || name == "target" { continue }
+            list_files(root, &path, out);
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            if let Some(rel) = rel.to_str() {
+                out.push(rel.replace('\\', "/"));
+            }
+        }
+    }
+}
+
+/// Check the manifests that apply to the crate in the given directory,
+/// without first loading them into a `Config`. Only the manifests are read,
+/// so aliases are not checked against the files of the crate.
+pub fn check_manifests(dir: &Path, flags: &FlagSet, args: &FlagSet) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(flags, args);
+    for path in config::find_manifests(dir) {
+        checker.check_file(&path);
+    }
+    checker.finish()
+}
@@ -0,0 +1,420 @@
+//! Loading and application of `Bag.toml` manifests.
+//!
+//! A crate inside a cargo workspace inherits the `Bag.toml` of the
+//! workspace root, and of any directories between the root and the crate.
+//! Tables are merged key by key, so a crate's manifest can override or
+//! extend individual entries. Relative paths are always resolved against
+//! the crate being built.
+
+use ::{BagRequest, Flag, Uri};
+use cfg::{Cfg, CfgSet};
+use solver::Budget;
+use bag::alias::{AliasTable, Glob};
+
+use failure::{Error, ResultExt};
+use toml::{self, Value};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// File name of the bagger manifest, found next to `Cargo.toml`.
+pub const MANIFEST_NAME: &str = "Bag.toml";
+
+/// Typed contents of a `Bag.toml` manifest.
+///
+/// Flags and arguments for a request are merged from several places. Each
+/// is only used if nothing before it sets the same flag or argument:
+///
+/// 1. the `bag!` macro (`+flag`, `?flag`, `%arg=(...)`)
+/// 2. matching `[asset."glob"]` sections, most specific first
+/// 3. matching `[target."cfg(...)"]` sections, in the order of their keys
+///    compared as strings (not the order they are written in)
+/// 4. the `[profile."name"]` section of the profile being built
+/// 5. `[defaults]`
+///
+/// Cargo only tells build scripts the target and profile being built, so a
+/// crate with `[target]` sections must forward the target from its build
+/// script (`cargo:rustc-env=TARGET=...`). If the profile is not forwarded
+/// (`cargo:rustc-env=PROFILE=...`), the `bag!` macro bags each request for
+/// both `dev` and `release`, and picks between them by
+/// `cfg(debug_assertions)`. Custom profiles only apply when forwarded. Set
+/// `BAGGER_REQUIRE_PROFILE` to make an unknown profile an error instead.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Flags and arguments applied to every request.
+    pub defaults: Defaults,
+    /// Compile-time URI aliases, from glob pattern to glob pattern.
+    pub alias: BTreeMap<String, String>,
+    /// Settings for assets loaded at run-time.
+    pub runtime: Runtime,
+    /// Flags and arguments keyed by `cfg(...)` predicate.
+    pub target: BTreeMap<String, Defaults>,
+    /// Flags and arguments keyed by cargo profile.
+    pub profile: BTreeMap<String, Defaults>,
+    /// Flags and arguments keyed by asset path.
+    pub asset: BTreeMap<String, Defaults>,
+    /// Loaders keyed by target type.
+    pub format: BTreeMap<String, Format>,
+    /// Limits on the solver.
+    pub search: Search,
+    /// Directory of the crate the config was loaded for.
+    #[serde(skip)]
+    pub root: PathBuf,
+    /// Manifests merged into this config, outermost first.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+/// The `[runtime]` table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Runtime {
+    /// Run-time URI aliases, from glob pattern to glob pattern.
+    pub alias: BTreeMap<String, String>,
+}
+
+/// The `[search]` table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Search {
+    /// Most nodes to expand while solving one request.
+    pub max_expansions: Option<usize>,
+    /// Longest time to spend solving one request, in milliseconds.
+    pub time_limit_ms: Option<u64>,
+}
+
+impl Search {
+    /// The solver budget, using the default for anything not set.
+    pub fn budget(&self) -> Budget {
+        let mut budget = Budget::default();
+        if let Some(n) = self.max_expansions {
+            budget.expansions = n;
+        }
+        if let Some(ms) = self.time_limit_ms {
+            budget.time = Some(Duration::from_millis(ms));
+        }
+        budget
+    }
+}
+
+/// A `[format."Type"]` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Format {
+    /// Path to the type which loads the format.
+    pub loader: String,
+}
+
+/// The build that requests are bagged for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Build {
+    /// The cfg options of the target, if known.
+    pub cfg: Option<CfgSet>,
+    /// The cargo profile, usually `dev` or `release`.
+    pub profile: Option<String>,
+}
+
+impl Build {
+    /// A build with no known target or profile.
+    pub fn new() -> Build {
+        Build::default()
+    }
+
+    /// Read the build from the environment. Like the target, cargo only
+    /// gives the profile to build scripts (`PROFILE` and `DEBUG`). If
+    /// neither is forwarded, the profile is unknown. A forwarded `PROFILE`
+    /// other than `debug` or `release` is taken as the name of a custom
+    /// profile.
+    pub fn from_env() -> Build {
+        let profile = match env::var("PROFILE") {
+            Ok(ref p) if p == "debug" => Some("dev".to_owned()),
+            Ok(ref p) if !p.is_empty() => Some(p.clone()),
+            _ => match env::var("DEBUG").as_ref().map(String::as_str) {
+                Ok("true") => Some("dev".to_owned()),
+                Ok("false") => Some("release".to_owned()),
+                _ => None,
+            },
+        };
+
+        Build {
+            cfg: CfgSet::from_env(),
+            profile,
+        }
+    }
+}
+
+/// A table of flags (`static = true`) and arguments
+/// (`content = "text/plain"`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Defaults(pub BTreeMap<String, Setting>);
+
+/// A single entry in a table of defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Setting {
+    /// Require (`true`) or forbid (`false`) a flag.
+    Flag(bool),
+    /// Set an argument.
+    Arg(String),
+}
+
+impl Defaults {
+    /// Fill in any flags or arguments not already set by the request.
+    pub fn apply(&self, req: &mut BagRequest) {
+        for (name, setting) in &self.0 {
+            let flag = Flag::from_str(name);
+            match *setting {
+                Setting::Flag(_) if req.required.contains(&flag)
+                    || req.forbidden.contains(&flag) => (),
+                Setting::Flag(true) => req.require_flag(flag),
+                Setting::Flag(false) => req.forbid_flag(flag),
+                Setting::Arg(ref val) => if !req.args.contains_key(&flag) {
+                    req.arg_flag(flag, val)
+                },
+            }
+        }
+    }
+}
+
+fn read_toml(path: &Path) -> Result<Value, Error> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .with_context(|_| format!("could not read \"{}\"", path.display()))?;
+    Ok(text.parse::<Value>()
+        .with_context(|_| format!("could not parse \"{}\"", path.display()))?)
+}
+
+fn is_workspace_root(dir: &Path) -> bool {
+    let cargo = dir.join("Cargo.toml");
+    if !cargo.is_file() { return false }
+    match read_toml(&cargo) {
+        Ok(Value::Table(t)) => t.contains_key("workspace"),
+        _ => false,
+    }
+}
+
+/// Merge `over` into `base`, replacing everything except tables, which are
+/// merged key by key.
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (&mut Value::Table(ref mut base), Value::Table(over)) => {
+            for (key, val) in over {
+                match base.get_mut(&key) {
+                    Some(b) => { merge(b, val); continue },
+                    None => (),
+                }
+                base.insert(key, val);
+            }
+        },
+        (base, over) => *base = over,
+    }
+}
+
+/// The directory with relative components resolved, so that its ancestors
+/// are the directories that contain it. Left as is if it can not be resolved.
+fn absolute(dir: &Path) -> PathBuf {
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_owned())
+}
+
+/// Find the manifests that apply to the crate in the given directory,
+/// outermost first. Manifests outside the crate are only used if the crate
+/// is inside a workspace. The paths found are absolute.
+pub fn find_manifests<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let dir = absolute(dir.as_ref());
+    let own = dir.join(MANIFEST_NAME);
+
+    let mut found = Vec::new();
+    let mut workspace = false;
+    for ancestor in dir.ancestors() {
+        let path = ancestor.join(MANIFEST_NAME);
+        if path.is_file() { found.push(path) }
+        if is_workspace_root(ancestor) {
+            workspace = true;
+            break
+        }
+    }
+
+    if !workspace {
+        found.retain(|p| *p == own);
+    }
+    found.reverse();
+    found
+}
+
+/// Every file that could change the result of `find_manifests`, whether or
+/// not it exists yet: the manifest and `Cargo.toml` in each directory it
+/// looks at.
+pub fn watched_paths<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for ancestor in absolute(dir.as_ref()).ancestors() {
+        paths.push(ancestor.join(MANIFEST_NAME));
+        paths.push(ancestor.join("Cargo.toml"));
+        if is_workspace_root(ancestor) { break }
+    }
+    paths
+}
+
+impl Config {
+    /// Parse the text of a manifest.
+    pub fn parse(text: &str) -> Result<Config, Error> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Load the manifests that apply to the crate in the given directory,
+    /// merged. If there are no manifests, an empty config is returned.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Config, Error> {
+        let dir = dir.as_ref();
+        let sources = find_manifests(dir);
+
+        let mut merged = Value::Table(Default::default());
+        for path in &sources {
+            merge(&mut merged, read_toml(path)?);
+        }
+
+        let mut config: Config = merged.try_into()
+            .with_context(|_| format!("invalid config in \"{}\"", dir.display()))?;
+        config.root = dir.to_owned();
+        config.sources = sources;
+        Ok(config)
+    }
+
+    /// Load the manifests of the crate currently being built, as given by
+    /// `CARGO_MANIFEST_DIR`.
+    pub fn from_env() -> Result<Config, Error> {
+        match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(dir) => Config::load(dir),
+            None => Ok(Config::default()),
+        }
+    }
+
+    /// Write the effective config as TOML.
+    pub fn to_toml(&self) -> Result<String, Error> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// The compile-time `[alias]` rules.
+    pub fn aliases(&self) -> Result<AliasTable, Error> {
+        let mut table = AliasTable::new();
+        for (from, to) in &self.alias {
+            table.insert(from, to)?;
+        }
+        Ok(table)
+    }
+
+    /// Rewrite a URI through the most specific matching `[alias]` rule. Local
+    /// files that an alias resolves to must exist.
+    pub fn resolve_alias(&self, uri: &Uri) -> Result<Uri, Error> {
+        let table = self.aliases()?;
+        let alias = match uri.path.to_str().and_then(|p| table.lookup(p)) {
+            Some(a) => a,
+            None => return Ok(uri.clone()),
+        };
+
+        let mut resolved = uri.clone();
+        resolved.path = PathBuf::from(&alias.path);
+
+        let local = match uri.scheme.as_ref().map(String::as_str) {
+            None | Some("file") | Some("files") => true,
+            _ => false,
+        };
+        if local && !self.root.join(&resolved.path).exists() {
+            bail!(
+                "alias \"{}\" = \"{}\" maps \"{}\" to missing file \"{}\"",
+                alias.from,
+                alias.to,
+                uri.path.display(),
+                alias.path,
+            )
+        }
+
+        Ok(resolved)
+    }
+
+    /// The `[runtime.alias]` rules.
+    pub fn runtime_aliases(&self) -> Result<AliasTable, Error> {
+        let mut table = AliasTable::new();
+        for (from, to) in &self.runtime.alias {
+            table.insert(from, to)?;
+        }
+        Ok(table)
+    }
+
+    /// Apply this config to a request, without overriding anything the
+    /// request sets explicitly. See `Config` for the merge order.
+    ///
+    /// The request's `runtime_path` is set from `[runtime.alias]`, for bags
+    /// that load the asset at run time.
+    pub fn apply(&self, req: &mut BagRequest, build: &Build) -> Result<(), Error> {
+        if let Some(path) = req.uri.path.to_str().map(ToOwned::to_owned) {
+            let mut assets = Vec::new();
+            for (key, defaults) in &self.asset {
+                let glob = Glob::from_str(key)
+                    .with_context(|_| format!("invalid asset \"{}\"", key))?;
+                if glob.matches(&path) {
+                    assets.push((glob.specificity(), defaults));
+                }
+            }
+            assets.sort_by(|a, b| b.0.cmp(&a.0));
+            for (_, defaults) in assets {
+                defaults.apply(req);
+            }
+        }
+
+        if !self.target.is_empty() {
+            let cfg = match build.cfg {
+                Some(ref cfg) => cfg,
+                None => bail!(
+                    "[target] sections need the target being built, which cargo only \
+                     gives to build scripts (forward it from build.rs with \
+                     \"cargo:rustc-env=TARGET=...\", see the docs of the bag crate)"
+                ),
+            };
+            for (key, defaults) in &self.target {
+                let pred = Cfg::from_str(key)
+                    .with_context(|_| format!("invalid target \"{}\"", key))?;
+                if pred.eval(cfg) {
+                    defaults.apply(req);
+                }
+            }
+        }
+        if !self.profile.is_empty() {
+            let profile = match build.profile {
+                Some(ref profile) => profile,
+                None => bail!(
+                    "[profile] sections need the profile being built, which cargo only \
+                     gives to build scripts (forward it with \"cargo:rustc-env=PROFILE=...\")"
+                ),
+            };
+            if let Some(defaults) = self.profile.get(profile) {
+                defaults.apply(req);
+            }
+        }
+        self.defaults.apply(req);
+
+        if req.runtime_path.is_none() {
+            let table = self.runtime_aliases()?;
+            req.runtime_path = req.uri.path.to_str().and_then(|p| table.resolve(p));
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Config {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for source in &self.sources {
+            writeln!(f, "# from {}", source.display())?;
+        }
+        match self.to_toml() {
+            Ok(text) => write!(f, "{}", text),
+            Err(e) => write!(f, "# could not write config: {}", e),
+        }
+    }
+}
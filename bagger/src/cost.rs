@@ -0,0 +1,91 @@
+//! Costs of edges, used to choose between solutions.
+//!
+//! Every edge costs something along three axes: time added to compilation,
+//! size added to the binary, and latency added at run time. The solver
+//! explores paths cheapest first, by the sum of their edge costs weighted by
+//! `Weights`, so a long chain of cheap edges can beat one expensive edge.
+//! Required flags are not costs; a path that can not satisfy them is never
+//! chosen, however cheap.
+//!
+//! Costs are relative, not measurements. A plugin should price its edges
+//! against the builtin ones:
+//!
+//! | builtin edge                          | compile | size | runtime |
+//! |---------------------------------------|---------|------|---------|
+//! | `include_str!` or `include_bytes!`    | 1       | 1    | 0       |
+//! | read a file during the build          | 1       | 0    | 0       |
+//! | byte or string literal from a read    | 2       | 1    | 0       |
+//! | decode with a `[format]` loader       | 0       | 0    | 1       |
+//! | load a file at run time               | 0       | 0    | 8       |
+//!
+//! Edges that only change how a node is described, such as `Request ->
+//! LocalPath`, should cost nothing. `EdgeBuilder::priority` breaks ties
+//! between paths of equal cost. An edge that joins other nodes also costs
+//! the paths to them.
+
+use std::fmt::{self, Display, Formatter};
+use std::ops::Add;
+
+/// The cost of an edge or path.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct Cost {
+    /// Time added to compilation.
+    pub compile: u32,
+    /// Size added to the binary.
+    pub size: u32,
+    /// Latency added at run time.
+    pub runtime: u32,
+}
+
+impl Cost {
+    pub fn new(compile: u32, size: u32, runtime: u32) -> Cost {
+        Cost { compile, size, runtime }
+    }
+
+    /// No cost at all.
+    pub fn zero() -> Cost {
+        Cost::default()
+    }
+}
+
+impl Add for Cost {
+    type Output = Cost;
+
+    fn add(self, other: Cost) -> Cost {
+        Cost {
+            compile: self.compile.saturating_add(other.compile),
+            size: self.size.saturating_add(other.size),
+            runtime: self.runtime.saturating_add(other.runtime),
+        }
+    }
+}
+
+impl Display for Cost {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "compile {}, size {}, runtime {}", self.compile, self.size, self.runtime)
+    }
+}
+
+/// How much each kind of cost matters when comparing paths.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Weights {
+    pub compile: u32,
+    pub size: u32,
+    pub runtime: u32,
+}
+
+/// Every kind of cost matters equally.
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights { compile: 1, size: 1, runtime: 1 }
+    }
+}
+
+impl Weights {
+    /// The total weighted cost.
+    pub fn weigh(&self, cost: Cost) -> u64 {
+        cost.compile as u64 * self.compile as u64
+            + cost.size as u64 * self.size as u64
+            + cost.runtime as u64 * self.runtime as u64
+    }
+}
@@ -3,6 +3,7 @@ use syn::punctuated::Punctuated;
 use syn::token;
 use quote::{Tokens, ToTokens};
 use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
 
 use failure::{Error, err_msg};
 
@@ -20,6 +21,31 @@ pub enum BagTrait {
     Async,
 }
 
+/// Write a type without the spaces `quote` puts between tokens.
+fn type_string<T: ToTokens>(ty: T) -> String {
+    let mut text = ty.into_tokens().to_string();
+    for &(from, to) in &[(" <", "<"), ("< ", "<"), (" >", ">"), ("[ ", "["), (" ]", "]"),
+        (" ::", "::"), (":: ", "::"), (" ,", ","), ("& ", "&")]
+    {
+        text = text.replace(from, to);
+    }
+    text
+}
+
+impl Display for BagTrait {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use self::BagTrait::*;
+
+        write!(f, "{}", match *self {
+            Simple => "Bag",
+            Try => "TryBag",
+            Unbag => "Unbag",
+            TryUnbag => "TryUnbag",
+            Async => "AsyncBag",
+        })
+    }
+}
+
 impl BagTrait {
     /// Converts this bag trait to the equivalent failable bag. For example, 
     /// `Bag` is converted into `TryBag`.
@@ -181,6 +207,17 @@ impl BagInfo {
     }
 }
 
+/// Written as rust syntax, such as `Bag<str> + Unbag<String>`.
+impl Display for BagInfo {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut bounds: Vec<_> = self.impls.iter()
+            .map(|&(tr, ref ty)| format!("{}<{}>", tr, type_string(ty)))
+            .collect();
+        bounds.sort();
+        write!(f, "{}", bounds.join(" + "))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BagType {
     pub full: Type,
@@ -227,13 +264,19 @@ impl ExprType {
     pub fn full(self) -> Type {
         let t = self.ok_type;
         if self.is_result {
-            parse_quote! { Result<#t, ::failure::Error> }
+            parse_quote! { Result<#t, ::bag::fail::Error> }
         } else {
             t
         }
     }
 }
 
+impl Display for ExprType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", type_string(self.clone().full()))
+    }
+}
+
 #[derive(Debug)]
 pub struct Expr {
     pub inputs: Vec<(Ident, Expr)>,
@@ -292,10 +335,12 @@ impl Expr {
             .unzip();
 
         let a_type = quote! { (#(#input_types,)*) };
-        let b_type = self.returns.ok_type;
+        let is_result = self.returns.is_result;
+        let b_type = self.returns.ok_type.clone();
+        let f_returns = self.returns.full();
         let b_expr = self.expr;
 
-        let bag_name = Ident::from(if self.returns.is_result {
+        let bag_name = Ident::from(if is_result {
             "TryLazyMap"
         } else {
             "LazyMap"
@@ -306,7 +351,7 @@ impl Expr {
                 ::bag::bags::#bag_name::<
                     #a_type,
                     #b_type,
-                    fn(#a_type) -> #b_type
+                    fn(#a_type) -> #f_returns
                 >::new(
                     (#(#input_exprs,)*),
                     |(#(#input_names,)*)| #b_expr
@@ -316,7 +361,7 @@ impl Expr {
                 ::bag::bags::#bag_name<
                     #a_type,
                     #b_type,
-                    fn(#a_type) -> #b_type
+                    fn(#a_type) -> #f_returns
                 >
             },
         }
@@ -0,0 +1,47 @@
+//! `${VAR}` interpolation of environment variables in URIs and arguments.
+
+use failure::Error;
+
+use std::collections::BTreeSet;
+use std::env;
+
+/// Replace every `${VAR}` in the text with the value of the environment
+/// variable `VAR`, adding `VAR` to `used`. A literal `${` is written `$${`.
+pub fn interpolate(text: &str, used: &mut BTreeSet<String>) -> Result<String, Error> {
+    interpolate_with(text, used, |name| env::var(name).ok())
+}
+
+/// Like `interpolate`, looking variables up with the given function instead
+/// of in the environment.
+pub fn interpolate_with<F>(text: &str, used: &mut BTreeSet<String>, lookup: F)
+    -> Result<String, Error>
+    where F: Fn(&str) -> Option<String>
+{
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start]);
+            out.push('{');
+            rest = &rest[start + 2..];
+            continue
+        }
+
+        out.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("unterminated \"${{\" in \"{}\"", text),
+        };
+        let name = &rest[start + 2..end];
+        if name.is_empty() { bail!("empty \"${{}}\" in \"{}\"", text) }
+
+        match lookup(name) {
+            Some(val) => out.push_str(&val),
+            None => bail!("environment variable \"{}\" is not set", name),
+        }
+        used.insert(name.to_owned());
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
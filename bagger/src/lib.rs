@@ -6,26 +6,49 @@ extern crate syn;
 extern crate lazy_static;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate toml;
+extern crate serde_json;
 extern crate mime;
 extern crate mime_guess;
 extern crate proc_macro2;
 extern crate easy_uri as uri;
+extern crate bag;
 
 pub mod expr;
 pub mod solver;
 pub mod flag;
 pub mod nodes;
+pub mod config;
+pub mod cfg;
+pub mod interp;
+pub mod check;
+pub mod report;
+pub mod cost;
+pub mod cache;
 mod builtins;
 
-pub use solver::{NodeInput, EdgeBuilder, Solution};
+pub use solver::{NodeInput, EdgeBuilder, EdgeKind, Inputs, Solution, Solutions, Budget};
 pub use flag::Flag;
 pub use nodes::Node;
 pub use uri::Uri;
 pub use expr::BagInfo;
+pub use config::{Config, Build};
+pub use check::Diagnostic;
+pub use report::{SearchGraph, NoSolution};
+pub use cost::{Cost, Weights};
 
 use flag::{FlagMap, FlagSet};
+use cache::{CacheKey, SolutionCache};
 use proc_macro2::Span;
 
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::env;
+use std::path::{Path, PathBuf};
+
 #[derive(Debug, Clone)]
 pub struct BagRequest {
     pub uri: Uri,
@@ -34,6 +57,11 @@ pub struct BagRequest {
     pub forbidden: FlagSet,
     pub args: FlagMap<String>,
     pub span: Span,
+    /// Environment variables the request depends on.
+    pub env: BTreeSet<String>,
+    /// Where bags that load the asset at run time look for it, if not at
+    /// its path. Set from `[runtime.alias]` by `Config::apply`.
+    pub runtime_path: Option<String>,
 }
 
 impl BagRequest {
@@ -45,6 +73,8 @@ impl BagRequest {
             forbidden: FlagSet::new(),
             args: FlagMap::new(),
             span: Span::call_site(),
+            env: BTreeSet::new(),
+            runtime_path: None,
         }
     }
 
@@ -73,19 +103,172 @@ impl BagRequest {
     pub fn arg(&mut self, flag: &str, val: &str) {
         self.arg_flag(Flag::from_str(flag), val)
     }
+
+    /// Replace `${VAR}` in the URI path and argument values with environment
+    /// variables, recording them in `env`. The URI is already parsed, so
+    /// variables in its scheme are not replaced; to allow those, interpolate
+    /// the URI text with `interp::interpolate` before parsing it.
+    pub fn interpolate(&mut self) -> Result<(), failure::Error> {
+        self.interpolate_with(|name| env::var(name).ok())
+    }
+
+    /// Like `interpolate`, looking variables up with the given function
+    /// instead of in the environment.
+    pub fn interpolate_with<F>(&mut self, lookup: F) -> Result<(), failure::Error>
+        where F: Fn(&str) -> Option<String>
+    {
+        if let Some(path) = self.uri.path.to_str().map(ToOwned::to_owned) {
+            let path = interp::interpolate_with(&path, &mut self.env, &lookup)?;
+            self.uri.path = PathBuf::from(path);
+        }
+        for val in self.args.values_mut() {
+            *val = interp::interpolate_with(val, &mut self.env, &lookup)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct Bagger {
     solver: solver::Solver,
+    config: Config,
+    build: Build,
+    flags: FlagSet,
+    args: FlagSet,
+    /// Arguments only some routes use.
+    route_args: FlagSet,
+    cache: RefCell<SolutionCache>,
 }
 
 impl Bagger {
+    /// Create a bagger with the builtin transforms and an empty config.
     pub fn new() -> Bagger {
+        Bagger::with_config(Config::default())
+            .expect("empty config is valid")
+    }
+
+    /// Create a bagger with the builtin transforms, plus the loaders given
+    /// by the config.
+    pub fn with_config(config: Config) -> Result<Bagger, failure::Error> {
         let mut bggr = Bagger {
             solver: solver::Solver::new(),
+            config: Config::default(),
+            build: Build::new(),
+            flags: FlagSet::new(),
+            args: FlagSet::new(),
+            route_args: FlagSet::new(),
+            cache: RefCell::new(SolutionCache::new()),
         };
         builtins::register_builtins(&mut bggr);
-        bggr
+        builtins::register_formats(&mut bggr, &config.format)?;
+        bggr.solver.budget = config.search.budget();
+        bggr.config = config;
+        bggr.prepare_joins();
+        Ok(bggr)
+    }
+
+    /// Create a bagger configured by the `Bag.toml`, target, and profile of
+    /// the crate currently being built.
+    pub fn from_env() -> Result<Bagger, failure::Error> {
+        let mut bggr = Bagger::with_config(Config::from_env()?)?;
+        bggr.set_build(Build::from_env());
+        Ok(bggr)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The build used to select `[target]` and `[profile]` sections.
+    pub fn build(&self) -> &Build {
+        &self.build
+    }
+
+    /// Set how edge costs are weighed when choosing between solutions.
+    pub fn set_weights(&mut self, weights: Weights) {
+        self.solver.weights = weights;
+    }
+
+    /// Set how much work the solver may do on one request before giving up.
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.solver.budget = budget;
+    }
+
+    /// Set the build used to select `[target]` and `[profile]` sections.
+    pub fn set_build(&mut self, build: Build) {
+        self.build = build;
+        self.prepare_joins();
+    }
+
+    /// Have the solver prepare joined requests like `prepare`.
+    fn prepare_joins(&mut self) {
+        let (config, build) = (self.config.clone(), self.build.clone());
+        self.solver.prepare = Some(Box::new(move |bag| {
+            config.apply(bag, &build)?;
+            config.resolve_alias(&bag.uri)
+        }));
+    }
+
+    /// Declare a flag that some transform satisfies or reads. Requests and
+    /// manifests using undeclared flags are reported by `check_request` and
+    /// `check_config`.
+    pub fn declare_flag(&mut self, flag: &str) {
+        self.flags.insert(Flag::from_str(flag));
+    }
+
+    /// Declare an argument that some transform reads.
+    pub fn declare_arg(&mut self, arg: &str) {
+        self.args.insert(Flag::from_str(arg));
+    }
+
+    /// Declare an argument that only some routes use, marked by
+    /// `EdgeBuilder::uses_arg`. Solutions that take another route are
+    /// reported by `check_solution`.
+    pub fn declare_route_arg(&mut self, arg: &str) {
+        self.declare_arg(arg);
+        self.route_args.insert(Flag::from_str(arg));
+    }
+
+    /// Check the manifests this bagger was configured from for unknown keys,
+    /// flags, and arguments, aliases that match no files, and invalid
+    /// loaders. A config not loaded from files is checked as a whole.
+    pub fn check_config(&self) -> Vec<Diagnostic> {
+        let mut checker = check::Checker::new(&self.flags, &self.args);
+        if self.config.sources.is_empty() {
+            if let Ok(value) = toml::Value::try_from(&self.config) {
+                checker.check_value(&value);
+            }
+        } else {
+            for source in &self.config.sources {
+                checker.check_file(source);
+            }
+        }
+        checker.check_alias_files(&self.config);
+        checker.finish()
+    }
+
+    /// Check the manifests that apply to the crate in the given directory.
+    /// Unlike `check_config`, this also reports manifests that can not be
+    /// loaded at all, but does not look for files matching the aliases, so
+    /// it is cheap enough to run on every build.
+    pub fn check_manifests<P: AsRef<Path>>(&self, dir: P) -> Vec<Diagnostic> {
+        check::check_manifests(dir.as_ref(), &self.flags, &self.args)
+    }
+
+    /// Check a request for unknown flags and arguments.
+    pub fn check_request(&self, req: &BagRequest) -> Vec<Diagnostic> {
+        let mut checker = check::Checker::new(&self.flags, &self.args);
+        checker.check_request(req);
+        checker.finish()
+    }
+
+    /// Check a solution to a request for arguments that its route ignores.
+    /// The config is applied to the request first, as when solving it.
+    pub fn check_solution(&self, req: &BagRequest, sol: &Solution) -> Vec<Diagnostic> {
+        let mut req = req.clone();
+        if self.config.apply(&mut req, &self.build).is_err() { return Vec::new() }
+        let mut checker = check::Checker::new(&self.flags, &self.args);
+        checker.check_solution(&req, sol, &self.route_args);
+        checker.finish()
     }
 
     #[inline(always)]
@@ -96,14 +279,88 @@ impl Bagger {
         self.solver.transforms.push(trans as Box<solver::TransformInstance>);
     }
 
+    /// Add a transform like `transform`, declaring every kind of edge it may
+    /// add. Searches for required flags skip nodes from which no declared
+    /// edge can lead to those flags. Undeclared edges are stopped.
+    pub fn transform_declared<N, F>(&mut self, edges: Vec<EdgeKind>, trans: F)
+        where N: Node, F: Fn(NodeInput<N>) + Send + 'static
+    {
+        let trans = Box::new(solver::FnTransform::declared(trans, edges));
+        self.solver.transforms.push(trans as Box<solver::TransformInstance>);
+    }
+
     pub fn terminal<T>(&mut self, term: T)
         where T: solver::Terminal + 'static
     {
         self.solver.terminals.push(Box::new(term) as _)
     }
 
-    #[inline(always)]
-    pub fn solve(&self, bag: BagRequest) -> Result<Solution, failure::Error> {
-        self.solver.solve(bag)
+    /// Apply the config to a request, and find where to start solving it.
+    fn prepare(&self, bag: &mut BagRequest) -> Result<Uri, failure::Error> {
+        self.config.apply(bag, &self.build)?;
+        self.config.resolve_alias(&bag.uri)
+    }
+
+    /// Apply the config to a request, then solve it starting from the
+    /// aliased URI.
+    pub fn solve(&self, mut bag: BagRequest) -> Result<Solution, failure::Error> {
+        let start = self.prepare(&mut bag)?;
+        self.solver.solve_at(start, bag)
+    }
+
+    /// Solve a request like `solve`, also returning every node the search
+    /// explored. The graph is empty if the search never started.
+    pub fn solve_with_graph(&self, mut bag: BagRequest)
+        -> (Result<Solution, failure::Error>, SearchGraph)
+    {
+        match self.prepare(&mut bag) {
+            Ok(start) => self.solver.search(start, bag),
+            Err(e) => (Err(e), SearchGraph::default()),
+        }
+    }
+
+    /// Solve a request like `solve_with_graph`, reusing the solution to an
+    /// identical earlier request if the file it starts from is unchanged.
+    /// Requests without a solution are searched again every time. Solutions
+    /// are remembered by this bagger only, and lose their spans (see
+    /// `cache`).
+    pub fn solve_cached(&self, mut bag: BagRequest)
+        -> (Result<Solution, failure::Error>, SearchGraph)
+    {
+        let start = match self.prepare(&mut bag) {
+            Ok(start) => start,
+            Err(e) => return (Err(e), SearchGraph::default()),
+        };
+        let key = CacheKey::new(&self.config.root, &start, &bag);
+        match self.cache.borrow().get(&key) {
+            Ok(Some((sol, graph))) => return (Ok(sol), graph),
+            Ok(None) => (),
+            Err(e) => return (Err(e), SearchGraph::default()),
+        }
+
+        let (sol, graph) = self.solver.search(start, bag);
+        if let Ok(ref sol) = sol {
+            self.cache.borrow_mut().insert(key, sol, &graph);
+        }
+        (sol, graph)
+    }
+
+    /// Number of solutions remembered by `solve_cached`.
+    pub fn cached(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    /// Forget every solution remembered by `solve_cached`.
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear()
+    }
+
+    /// Apply the config to a request, then find every solution to it,
+    /// cheapest first.
+    pub fn solve_all<'b>(&'b self, mut bag: BagRequest)
+        -> Result<Solutions<'b>, failure::Error>
+    {
+        let start = self.prepare(&mut bag)?;
+        Ok(self.solver.solve_all_at(start, bag))
     }
 }
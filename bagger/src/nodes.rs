@@ -5,43 +5,140 @@ use expr::{Expr, ExprType, BagInfo, BagExpr, GenericBagExpr};
 use mime::Mime;
 
 use std::path::PathBuf;
-use std::any::Any;
+use std::any::{Any, type_name};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io;
 
 /// Type that defines a node.
 pub trait Node: 'static {
     type Target: 'static;
+
+    /// Describe this node for humans, such as in solver reports. Defaults to
+    /// the name of the type.
+    fn describe(&self) -> String {
+        short_type_name::<Self>()
+    }
+
+    /// Identify this node, so that the solver only expands one of several
+    /// equivalent nodes of the same type. Nodes without a key are never
+    /// considered equivalent. Nodes whose value depends on the path taken to
+    /// them, such as expressions, should not have a key. See `node_key`.
+    fn key(&self) -> Option<NodeKey> {
+        None
+    }
+}
+
+/// The name of a type, without its module path.
+pub fn short_type_name<T: ?Sized>() -> String {
+    let name = type_name::<T>();
+    // strip the module path, but not from type parameters
+    let end = name.find('<').unwrap_or(name.len());
+    let start = name[..end].rfind("::").map(|i| i + 2).unwrap_or(0);
+    name[start..].to_owned()
+}
+
+/// A `Node::key`. Keys are looked up by hash, but compared by value, so
+/// distinct keys with the same hash are never taken as equivalent.
+pub struct NodeKey {
+    hash: u64,
+    val: Box<Any>,
+    eq: fn(&Any, &Any) -> bool,
+}
+
+impl NodeKey {
+    pub fn new<T: Hash + Eq + 'static>(val: T) -> NodeKey {
+        fn eq<T: Eq + 'static>(a: &Any, b: &Any) -> bool {
+            match (a.downcast_ref::<T>(), b.downcast_ref::<T>()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        val.hash(&mut hasher);
+        NodeKey { hash: hasher.finish(), val: Box::new(val), eq: eq::<T> }
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl PartialEq for NodeKey {
+    fn eq(&self, other: &NodeKey) -> bool {
+        self.hash == other.hash && (self.eq)(&*self.val, &*other.val)
+    }
+}
+
+impl Eq for NodeKey {}
+
+/// Make a `Node::key` from a value.
+pub fn node_key<T: Hash + Eq + 'static>(val: T) -> Option<NodeKey> {
+    Some(NodeKey::new(val))
 }
 
 /// The starting node, a basic asset request.
 pub struct Request(pub Uri);
 impl Node for Request {
     type Target = ();
+
+    fn describe(&self) -> String {
+        format!("Request({})", self.0.path.display())
+    }
+
+    fn key(&self) -> Option<NodeKey> {
+        node_key((self.0.scheme.clone(), self.0.path.clone()))
+    }
 }
 
 pub struct LocalPath(pub PathBuf);
 impl Node for LocalPath {
     type Target = ();
+
+    fn describe(&self) -> String {
+        format!("LocalPath({})", self.0.display())
+    }
+
+    fn key(&self) -> Option<NodeKey> {
+        node_key(self.0.clone())
+    }
 }
 
 pub struct LocalRead(pub Mime);
 impl Node for LocalRead {
     type Target = Box<io::Read>;
+
+    fn describe(&self) -> String {
+        format!("LocalRead({})", self.0)
+    }
 }
 
 pub struct ReadData(pub Mime);
 impl Node for ReadData {
     type Target = Expr;
+
+    fn describe(&self) -> String {
+        format!("ReadData({})", self.0)
+    }
 }
 
 pub struct Data(pub ExprType);
 impl Node for Data {
     type Target = Expr;
+
+    fn describe(&self) -> String {
+        format!("Data({})", self.0)
+    }
 }
 
 pub struct Producer(pub BagInfo);
 impl Node for Producer {
     type Target = BagExpr;
+
+    fn describe(&self) -> String {
+        format!("Producer({})", self.0)
+    }
 }
 
 impl Producer {
@@ -64,10 +161,8 @@ impl Terminal for EndOnProducer {
     }
 
     fn extract(&self, _: Working, n: Box<Any>) -> Solution {
-        Solution { 
-            bag_expr: *n.downcast::<<Producer as Node>::Target>()
-                .unwrap(),
-        }
+        Solution::new(*n.downcast::<<Producer as Node>::Target>()
+            .unwrap())
     }
 }
 
@@ -83,11 +178,9 @@ impl Terminal for EndOnGenericProducer {
     }
 
     fn extract(&self, w: Working, n: Box<Any>) -> Solution {
-        Solution { 
-            bag_expr: n.downcast::<<GenericProducer as Node>::Target>()
-                .unwrap()
-                .eval_to_bag(&w.target),
-        }
+        Solution::new(n.downcast::<<GenericProducer as Node>::Target>()
+            .unwrap()
+            .eval_to_bag(&w.target))
     }
 }
 
@@ -104,9 +197,7 @@ impl Terminal for EndOnTerminate {
     }
 
     fn extract(&self, _: Working, n: Box<Any>) -> Solution {
-        Solution { 
-            bag_expr: *n.downcast::<<Terminate as Node>::Target>()
-                .unwrap(),
-        }
+        Solution::new(*n.downcast::<<Terminate as Node>::Target>()
+            .unwrap())
     }
 }
@@ -0,0 +1,167 @@
+//! Reports of the search made by the solver.
+
+use cost::Cost;
+
+use failure::{Error, Fail};
+use serde_json;
+
+use std::fmt::{self, Display, Formatter, Write};
+
+/// A node explored while solving a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SearchNode {
+    /// Index of the node in the search, in the order nodes were created.
+    pub index: usize,
+    /// The node this one was reached from. The `Request` has no parent.
+    pub parent: Option<usize>,
+    /// Description of the node, from `Node::describe`.
+    pub name: String,
+    /// Required flags satisfied on the way to this node.
+    pub satisfies: Vec<String>,
+    /// Queue priority of the edge to this node.
+    pub priority: i32,
+    /// Cost of the path to this node.
+    pub cost: Cost,
+    /// Why the edge to this node can not be taken. Edges without a reason
+    /// are active.
+    pub stop: Option<String>,
+    /// An equivalent node that was explored instead of this one.
+    pub duplicate: Option<usize>,
+    /// The paths taken to the nodes this one joins, from other requests.
+    pub joined: Vec<String>,
+}
+
+/// Every node explored while solving a request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SearchGraph {
+    pub nodes: Vec<SearchNode>,
+    /// The node a terminal accepted, if any.
+    pub end: Option<usize>,
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl SearchGraph {
+    /// The nodes from the request to the given node, request first.
+    pub fn path(&self, index: usize) -> Vec<&SearchNode> {
+        let mut path = Vec::new();
+        let mut next = Some(index);
+        while let Some(i) = next {
+            let node = &self.nodes[i];
+            path.push(node);
+            next = node.parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Write the graph in the Graphviz DOT language. Stopped edges are dashed
+    /// and labeled with the reason, and the path to the accepted node is bold.
+    pub fn to_dot(&self) -> String {
+        let on_path: Vec<_> = self.end
+            .map(|end| self.path(end).iter().map(|n| n.index).collect())
+            .unwrap_or_default();
+
+        let mut dot = String::new();
+        writeln!(dot, "digraph search {{").unwrap();
+        writeln!(dot, "    node [shape=box];").unwrap();
+        for node in &self.nodes {
+            let mut label = escape_dot(&node.name);
+            if node.cost != Cost::zero() {
+                label.push_str("\\n");
+                label.push_str(&node.cost.to_string());
+            }
+            if !node.satisfies.is_empty() {
+                label.push_str("\\n");
+                label.push_str(&escape_dot(&node.satisfies.join(", ")));
+            }
+            let style = if on_path.contains(&node.index) { ", style=bold" } else { "" };
+            writeln!(dot, "    n{} [label=\"{}\"{}];", node.index, label, style).unwrap();
+
+            let parent = match node.parent {
+                Some(p) => p,
+                None => continue,
+            };
+            let attrs = match node.stop {
+                Some(ref stop) => format!(
+                    "label=\"{}: {}\", style=dashed, color=red",
+                    node.priority,
+                    escape_dot(stop),
+                ),
+                None => format!("label=\"{}\"{}", node.priority, style),
+            };
+            writeln!(dot, "    n{} -> n{} [{}];", parent, node.index, attrs).unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// Write the graph as JSON.
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Nodes that no other node was reached from.
+    pub fn leaves(&self) -> Vec<&SearchNode> {
+        let mut is_parent = vec![false; self.nodes.len()];
+        for node in &self.nodes {
+            if let Some(p) = node.parent { is_parent[p] = true }
+        }
+        self.nodes.iter()
+            .filter(|n| !is_parent[n.index])
+            .collect()
+    }
+}
+
+/// Most paths written when displaying a graph.
+const MAX_PATHS: usize = 32;
+
+/// Writes every explored path, followed by the reason each of its dead-end
+/// edges was stopped.
+impl Display for SearchGraph {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let leaves = self.leaves();
+        for leaf in leaves.iter().take(MAX_PATHS) {
+            let path = self.path(leaf.index);
+            let names: Vec<_> = path.iter().map(|n| n.name.as_str()).collect();
+            writeln!(f, "  {}", names.join(" -> "))?;
+
+            let mut stopped = false;
+            for node in &path {
+                if let Some(ref stop) = node.stop {
+                    writeln!(f, "      stopped at {}: {}", node.name, stop)?;
+                    stopped = true;
+                }
+            }
+            if let Some(dup) = leaf.duplicate {
+                writeln!(f, "      {} was already explored as node {}", leaf.name, dup)?;
+            } else if !stopped {
+                writeln!(f, "      nothing follows {}", leaf.name)?;
+            }
+        }
+        if leaves.len() > MAX_PATHS {
+            writeln!(f, "  ...and {} more", leaves.len() - MAX_PATHS)?;
+        }
+        Ok(())
+    }
+}
+
+/// A request could not be solved.
+#[derive(Debug)]
+pub struct NoSolution {
+    /// Why the search failed.
+    pub reason: String,
+    /// Everything the search tried.
+    pub graph: SearchGraph,
+}
+
+impl Display for NoSolution {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.reason)?;
+        write!(f, "explored paths:\n{}", self.graph)
+    }
+}
+
+impl Fail for NoSolution {}
@@ -1,22 +1,35 @@
-use ::{Node, BagRequest, Flag, nodes};
+use ::{Node, BagRequest, Flag, Uri, nodes};
+use nodes::NodeKey;
 use flag::{FlagMap, FlagSet};
 use expr::{BagExpr, BagInfo};
+use report::{NoSolution, SearchGraph, SearchNode};
+use cost::{Cost, Weights};
 
 use proc_macro2::Span;
-use failure::Error;
+use failure::{Error, ResultExt};
 
-use std::collections::{BTreeMap, VecDeque};
-use std::any::{Any};
-use std::cmp::{PartialOrd, Ord, Ordering};
+use std::collections::{BinaryHeap, BTreeSet, HashMap};
+use std::mem;
+use std::any::{Any, TypeId};
+use std::time::{Duration, Instant};
+use std::cmp::{self, PartialOrd, Ord, Ordering, Reverse};
 use std::marker::PhantomData;
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::rc::Rc;
 
-/// Priority for node exploration.
+/// Priority for node exploration. Greater orders are explored first.
+///
+/// Required flags are not part of the order. A terminal reached without them
+/// is passed over, so the first acceptable terminal is on the cheapest path
+/// that satisfies them.
 #[derive(PartialEq, Eq)]
 pub struct EdgeOrder {
     /// Most significant property: is the edge an active route?
     pub active: bool,
-    /// Second most significant property: how many requirements have been satisfied?
-    pub satisfies: u32,
+    /// Second most significant property: what is the weighted cost of the
+    /// whole path? Cheaper paths are explored first.
+    pub cost: u64,
     /// Least significant property: what is the exploration priority?
     pub priority: i32,
 }
@@ -24,12 +37,12 @@ pub struct EdgeOrder {
 impl Ord for EdgeOrder {
     fn cmp(&self, other: &EdgeOrder) -> Ordering {
         self.active.cmp(&other.active)
-            .then(self.satisfies.cmp(&other.satisfies))
+            .then(other.cost.cmp(&self.cost))
             .then(self.priority.cmp(&other.priority))
     }
 }
 
-fn default_val(_: Box<Any>) -> Result<Box<Any>, Error> {
+fn default_val(_: Box<Any>, _: Inputs) -> Result<Box<Any>, Error> {
     Ok(Box::new(()) as Box<Any>)
 }
 
@@ -37,16 +50,144 @@ fn get_node(nodes: &[Option<NodeInstance>], index: usize) -> &NodeInstance {
     nodes[index].as_ref().expect("early backtrace")
 }
 
+fn unreachable_message(flag: Flag) -> String {
+    format!("missing flag \"{}\", which nothing after this node satisfies", flag)
+}
+
 impl PartialOrd for EdgeOrder {
     fn partial_cmp(&self, other: &EdgeOrder) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
+/// Most joins nested inside the requests of other joins.
+const MAX_JOIN_DEPTH: usize = 8;
+
+/// Limits on how much work a search may do before giving up, so that
+/// transforms which feed back into each other can not hang the build.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Budget {
+    /// Most nodes to expand.
+    pub expansions: usize,
+    /// Longest time to search.
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget {
+            expansions: 10_000,
+            time: None,
+        }
+    }
+}
+
+/// A kind of edge that a transform may add: the type of node it leads to,
+/// and the flags it may satisfy.
+#[derive(Debug, Clone)]
+pub struct EdgeKind {
+    kind: TypeId,
+    flags: FlagSet,
+}
+
+impl EdgeKind {
+    pub fn to<N: Node>(flags: &[&str]) -> EdgeKind {
+        EdgeKind {
+            kind: TypeId::of::<N>(),
+            flags: flags.iter().map(|f| Flag::from_str(f)).collect(),
+        }
+    }
+}
+
+/// The flags that may still be satisfied after reaching a node of each type,
+/// as far as the transforms declare their edges.
+struct Reach {
+    /// `None` for types with transforms that do not declare their edges.
+    flags: HashMap<TypeId, Option<FlagSet>>,
+    /// Is there a transform that might apply to any type?
+    unbounded: bool,
+}
+
+impl Reach {
+    fn new(transforms: &[Box<TransformInstance>]) -> Reach {
+        let mut flags: HashMap<TypeId, Option<FlagSet>> = HashMap::new();
+        let mut unbounded = false;
+        for t in transforms {
+            match t.input() {
+                Some(input) => {
+                    let reach = flags.entry(input).or_insert_with(|| Some(FlagSet::new()));
+                    if t.edges().is_none() { *reach = None }
+                },
+                None => unbounded = true,
+            }
+        }
+
+        // add the flags of each edge, and of everything after it, until
+        // nothing changes
+        let mut changed = !unbounded;
+        while changed {
+            changed = false;
+            for t in transforms {
+                let (input, edges) = match (t.input(), t.edges()) {
+                    (Some(input), Some(edges)) => (input, edges),
+                    _ => continue,
+                };
+                let mut gained = FlagSet::new();
+                let mut open = false;
+                for edge in edges {
+                    gained.extend(&edge.flags);
+                    match flags.get(&edge.kind) {
+                        Some(&Some(ref after)) => gained.extend(after),
+                        Some(&None) => open = true,
+                        // no transforms leave this type
+                        None => (),
+                    }
+                }
+                let next = match flags[&input] {
+                    None => continue,
+                    Some(_) if open => None,
+                    Some(ref reach) if gained.is_subset(reach) => continue,
+                    Some(ref reach) => Some(reach.union(&gained).cloned().collect()),
+                };
+                flags.insert(input, next);
+                changed = true;
+            }
+        }
+
+        Reach { flags, unbounded }
+    }
+
+    /// Might the flag be satisfied on some path onward from a node of the
+    /// given type?
+    fn may_satisfy(&self, kind: TypeId, flag: &Flag) -> bool {
+        if self.unbounded { return true }
+        match self.flags.get(&kind) {
+            Some(&Some(ref reach)) => reach.contains(flag),
+            Some(&None) => true,
+            None => false,
+        }
+    }
+
+    /// A required flag that nothing onward from a node of the given type
+    /// satisfies, if there is one.
+    fn missing(&self, kind: TypeId, satisfies: &FlagSet, required: &FlagSet) -> Option<Flag> {
+        required.difference(satisfies)
+            .find(|f| !self.may_satisfy(kind, f))
+            .cloned()
+    }
+}
+
 /// Stores and solves asset transform graph.
 pub struct Solver {
     pub transforms: Vec<Box<TransformInstance>>,
     pub terminals: Vec<Box<Terminal>>,
+    /// How edge costs are weighed against each other.
+    pub weights: Weights,
+    pub budget: Budget,
+    /// Applies defaults to a joined request and picks the URI its search
+    /// starts from, as done for the request being solved. Joins start from
+    /// the URI they name if unset.
+    pub prepare: Option<Box<Fn(&mut BagRequest) -> Result<Uri, Error>>>,
 }
 
 impl Solver {
@@ -59,77 +200,411 @@ impl Solver {
                 Box::new(EndOnProducer) as _,
                 Box::new(EndOnGenericProducer) as _,
                 Box::new(EndOnTerminate) as _],
+            weights: Weights::default(),
+            budget: Budget::default(),
+            prepare: None,
         }
     }
 
     pub fn solve(&self, bag: BagRequest) -> Result<Solution, Error> {
+        let start = bag.uri.clone();
+        self.solve_at(start, bag)
+    }
+
+    /// Solve a request, starting the search from the given URI rather than
+    /// the requested one.
+    pub fn solve_at(&self, start: Uri, bag: BagRequest) -> Result<Solution, Error> {
+        self.search(start, bag).0
+    }
+
+    /// Solve a request like `solve_at`, also returning every node the search
+    /// explored.
+    pub fn search(&self, start: Uri, bag: BagRequest) -> (Result<Solution, Error>, SearchGraph) {
+        let mut all = self.solve_all_at(start, bag);
+        let sol = all.next();
+        let graph = all.graph();
+        let result = match sol {
+            Some(sol) => Ok(sol),
+            None => Err(NoSolution { reason: all.reason(), graph: graph.clone() }.into()),
+        };
+        (result, graph)
+    }
+
+    /// Find every solution to a request, cheapest first.
+    pub fn solve_all<'s>(&'s self, bag: BagRequest) -> Solutions<'s> {
+        let start = bag.uri.clone();
+        self.solve_all_at(start, bag)
+    }
+
+    /// Find every solution to a request like `solve_all`, starting the search
+    /// from the given URI rather than the requested one.
+    pub fn solve_all_at<'s>(&'s self, start: Uri, bag: BagRequest) -> Solutions<'s> {
+        self.start(start, bag, None, 0)
+    }
+
+    /// Begin a search. Searches with a goal accept the first node of that
+    /// type rather than asking the terminals.
+    fn start<'s>(&'s self, start: Uri, bag: BagRequest, goal: Option<Goal>, depth: usize)
+        -> Solutions<'s>
+    {
+        let start = nodes::Request(start);
         let start = NodeInstance {
-            data: Box::new(nodes::Request(bag.uri)) as _,
+            name: start.describe(),
+            kind: TypeId::of::<nodes::Request>(),
+            key: start.key(),
+            data: Box::new(start) as _,
             parent: 0,
+            dead: false,
+            failed: false,
+            duplicate: None,
+            rejected: None,
             satisfies: FlagSet::new(),
+            edge_satisfies: FlagSet::new(),
+            edge_args: FlagSet::new(),
+            priority: 0,
+            cost: Cost::zero(),
+            joins: Vec::new(),
+            joined: Vec::new(),
             value: Ok(Box::new(default_val) as _),
         };
         let mut work = Working {
+            uri: bag.uri,
             span: bag.span,
             args: bag.args,
+            runtime_path: bag.runtime_path,
             nodes: vec![Some(start)],
             new_nodes: Vec::new(),
             queue: WorkingQueue::new(),
+            weights: self.weights,
             target: bag.target,
             required: bag.required,
             forbidden: bag.forbidden,
+            depth,
+            kept: RefCell::new(HashMap::new()),
+            reach: Rc::new(Reach::new(&self.transforms)),
+            unmet: None,
+            stopped: None,
         };
-        let mut init_queue = VecDeque::new();
-        init_queue.push_back(0);
-        work.queue.insert(EdgeOrder {
+        work.queue.push((EdgeOrder {
             active: true,
-            satisfies: 0,
+            cost: 0,
             priority: 0,
-        }, init_queue);
+        }, Reverse(0)));
+
+        Solutions {
+            solver: self,
+            work,
+            started: Instant::now(),
+            expansions: 0,
+            expanded: HashMap::new(),
+            exhausted: None,
+            failure: None,
+            end: None,
+            accepted: None,
+            goal,
+        }
+    }
+
+    /// Solve the requests joined by a node, starting from the request it is
+    /// on. Returns the searches that reached each joined node, and the cost
+    /// of the paths to them.
+    fn join(&self, work: &Working, joins: &[Join]) -> Result<(Vec<(Working, usize)>, Cost), Error> {
+        let mut joined = Vec::new();
+        let mut cost = Cost::zero();
+        for join in joins {
+            if work.depth >= MAX_JOIN_DEPTH {
+                bail!("joins nested more than {} deep", MAX_JOIN_DEPTH)
+            }
+            // arguments are given per asset, so the joined request only gets
+            // those its own URI is configured with
+            let mut bag = BagRequest {
+                uri: join.uri.clone(),
+                target: BagInfo::empty(),
+                required: FlagSet::new(),
+                forbidden: work.forbidden.clone(),
+                args: FlagMap::new(),
+                span: work.span,
+                env: BTreeSet::new(),
+                runtime_path: None,
+            };
+            let start = match self.prepare {
+                Some(ref prepare) => prepare(&mut bag).with_context(|_| format!(
+                    "could not join {} from \"{}\"",
+                    join.name,
+                    join.uri.path.display(),
+                ))?,
+                None => join.uri.clone(),
+            };
+            let goal = Goal { kind: join.kind, name: join.name.clone() };
+            let mut sub = self.start(start, bag, Some(goal), work.depth + 1);
+            match sub.next_accepted() {
+                Some((end, _, _)) => {
+                    let sub = sub.into_working();
+                    cost = cost + get_node(&sub.nodes, end).cost;
+                    joined.push((sub, end));
+                },
+                None => bail!(
+                    "could not join {} from \"{}\": {}",
+                    join.name,
+                    join.uri.path.display(),
+                    sub.reason(),
+                ),
+            }
+        }
+        Ok((joined, cost))
+    }
+}
+
+/// The type of node a search for a joined input looks for.
+struct Goal {
+    kind: TypeId,
+    name: String,
+}
+
+/// A node of some type, to be reached from another request and given to an
+/// edge along with the value of its parent.
+struct Join {
+    uri: Uri,
+    kind: TypeId,
+    name: String,
+}
+
+/// The values of the nodes an edge joins, in the order they were joined.
+pub struct Inputs {
+    values: Vec<Option<Box<Any>>>,
+}
+
+impl Inputs {
+    /// Take the value of a joined node, by the index `EdgeBuilder::join`
+    /// returned.
+    pub fn take<N: Node>(&mut self, index: usize) -> Result<N::Target, Error> {
+        let value = match self.values.get_mut(index).and_then(Option::take) {
+            Some(v) => v,
+            None => bail!("no joined value {}", index),
+        };
+        match value.downcast::<N::Target>() {
+            Ok(v) => Ok(*v),
+            Err(_) => bail!("joined value {} has the wrong type", index),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Every solution to a request, cheapest first. The search only goes as far
+/// as the solutions taken from it.
+pub struct Solutions<'s> {
+    solver: &'s Solver,
+    work: Working,
+    started: Instant,
+    expansions: usize,
+    /// Expanded nodes with a key, by type and hash of the key.
+    expanded: HashMap<(TypeId, u64), Vec<usize>>,
+    exhausted: Option<String>,
+    failure: Option<String>,
+    /// The last node a terminal accepted.
+    end: Option<usize>,
+    /// An accepted node that has not been expanded yet.
+    accepted: Option<usize>,
+    goal: Option<Goal>,
+}
+
+impl<'s> Solutions<'s> {
+    /// Report every node explored so far. The end of the graph is the last
+    /// solution found.
+    pub fn graph(&self) -> SearchGraph {
+        let mut graph = self.work.graph();
+        graph.end = self.end;
+        graph
+    }
+
+    /// Why the search found no more solutions.
+    pub fn reason(&self) -> String {
+        self.exhausted.clone()
+            .or_else(|| self.failure.clone())
+            .or_else(|| self.work.unmet.map(|f| format!("no solution with flag \"{}\"", f)))
+            .or_else(|| self.work.stopped.clone())
+            .or_else(|| self.goal.as_ref().map(|g| format!("nothing reaches {}", g.name)))
+            .unwrap_or_else(|| "no solution (try adding more bagger plugins!)".to_owned())
+    }
+
+    fn into_working(self) -> Working {
+        self.work
+    }
+}
+
+impl<'s> Iterator for Solutions<'s> {
+    type Item = Solution;
+
+    fn next(&mut self) -> Option<Solution> {
+        let (node, terminal, val) = self.next_accepted()?;
+        let terminal = &self.solver.terminals[terminal.expect("goal reached without terminal")];
+        let mut sol = terminal.extract(self.work.snapshot(), val);
+        sol.path = self.work.path(node);
+        sol.joined = self.work.joined_uris(node);
+        Some(sol)
+    }
+}
+
+impl<'s> Solutions<'s> {
+    /// Search for the next node accepted by a terminal or the goal, returning
+    /// the index of the terminal and the value of the node.
+    fn next_accepted(&mut self) -> Option<(usize, Option<usize>, Box<Any>)> {
+        // routes may continue past the last accepted node
+        if let Some(node) = self.accepted.take() {
+            for t in &self.solver.transforms {
+                t.apply(&mut self.work, node);
+            }
+        }
+        if self.exhausted.is_some() { return None }
+
+        loop {
+            let work = &mut self.work;
 
-        let mut endpoint = None;
-        'bfs: loop {
             // append all new nodes
             work.nodes.extend(&mut work.new_nodes.drain(..).map(Option::from));
 
             // find next node in queue, starting with the highest priority
-            let mut node = None;
-            for (_, q) in work.queue.iter_mut().rev() {
-                node = q.pop_front();
-                if node.is_some() { break };
+            let node = match work.queue.pop() {
+                Some((_, Reverse(n))) => n,
+                None => return None,
+            };
+
+            // nothing can come of a path where some value failed
+            if work.is_failed(node) { continue }
+
+            // nor of one that can no longer satisfy the required flags.
+            // Edges are checked as they are added, so this only prunes the
+            // request itself
+            let unreachable = {
+                let nodei = get_node(&work.nodes, node);
+                work.reach.missing(nodei.kind, &nodei.satisfies, &work.required)
+            };
+            if let Some(flag) = unreachable {
+                work.nodes[node].as_mut().unwrap().rejected = Some(unreachable_message(flag));
+                work.unmet.get_or_insert(flag);
+                continue
             }
-            let node = match node {
-                Some(n) => n,
-                None => break,
+
+            // solve the requests this node joins, then explore it again in
+            // order of its total cost
+            let joins = {
+                let nodei = work.nodes[node].as_mut().unwrap();
+                mem::replace(&mut nodei.joins, Vec::new())
             };
+            if !joins.is_empty() {
+                match self.solver.join(work, &joins) {
+                    Ok((joined, cost)) => {
+                        let nodei = work.nodes[node].as_mut().unwrap();
+                        nodei.cost = nodei.cost + cost;
+                        nodei.joined = joined;
+                        work.queue.push((EdgeOrder {
+                            active: true,
+                            cost: work.weights.weigh(nodei.cost),
+                            priority: nodei.priority,
+                        }, Reverse(node)));
+                    },
+                    Err(e) => {
+                        self.failure.get_or_insert_with(|| e.to_string());
+                        work.fail(node, e);
+                    },
+                }
+                continue
+            }
 
-            // is solved yet?
-            {
+            // skip nodes equivalent to one already expanded with at least
+            // the same flags
+            let duplicate = {
                 let nodei = get_node(&work.nodes, node);
-                for ter in &self.terminals {
-                    if ter.terminate(&work, nodei) {
-                        endpoint = Some((node, ter));
-                        break 'bfs
-                    }
+                match nodei.key {
+                    Some(ref key) => {
+                        // nodes with the same hash may still differ
+                        let seen = self.expanded.entry((nodei.kind, key.hash())).or_insert_with(Vec::new);
+                        let dup = seen.iter().cloned().find(|&i| {
+                            let other = get_node(&work.nodes, i);
+                            other.key.as_ref() == Some(key)
+                                && !work.is_failed(i)
+                                && other.satisfies.is_superset(&nodei.satisfies)
+                        });
+                        if dup.is_none() { seen.push(node) }
+                        dup
+                    },
+                    None => None,
                 }
+            };
+            if let Some(dup) = duplicate {
+                work.nodes[node].as_mut().unwrap().duplicate = Some(dup);
+                continue
             }
 
-            // make next search layer
-            for t in &self.transforms {
-                t.apply(&mut work, node);
+            // give up rather than hang the build
+            let budget = self.solver.budget;
+            self.expansions += 1;
+            if self.expansions > budget.expansions {
+                self.exhausted = Some(format!(
+                    "search gave up after expanding {} nodes",
+                    budget.expansions,
+                ));
+                return None
+            }
+            if let Some(time) = budget.time {
+                if self.started.elapsed() > time {
+                    self.exhausted = Some(format!(
+                        "search gave up after {} ms",
+                        time.as_secs() * 1000 + time.subsec_nanos() as u64 / 1_000_000,
+                    ));
+                    return None
+                }
             }
-        }
 
-        if let Some((endpoint, terminal)) = endpoint {
-            // missing requirements
-            if let Some(missing) = work.required
-                .difference(&get_node(&work.nodes, endpoint).satisfies)
-                .next()
-            { bail!("no solution with flag \"{}\"", missing)}
+            // is solved yet? terminals without the required flags are
+            // passed over, since another path may satisfy them
+            let (missing, value_failed) = {
+                let nodei = get_node(&work.nodes, node);
+                let accepted = match self.goal {
+                    Some(ref goal) => if nodei.kind == goal.kind { Some(None) } else { None },
+                    None => self.solver.terminals.iter()
+                        .position(|ter| ter.terminate(&*work, nodei))
+                        .map(Some),
+                };
+                match accepted {
+                    Some(ter) => match work.required.difference(&nodei.satisfies).next() {
+                        Some(&missing) => (Some(missing), None),
+                        None => match work.eval(node) {
+                            Ok(val) => {
+                                self.end = Some(node);
+                                self.accepted = Some(node);
+                                return Some((node, ter, val))
+                            },
+                            Err(failed) => (None, Some(failed)),
+                        },
+                    },
+                    None => (None, None),
+                }
+            };
+            if let Some(missing) = missing {
+                work.nodes[node].as_mut().unwrap().rejected =
+                    Some(format!("missing flag \"{}\"", missing));
+                work.unmet.get_or_insert(missing);
+            }
+            // resume the search without the node whose value failed
+            if let Some((failed, e)) = value_failed {
+                self.failure.get_or_insert_with(|| e.to_string());
+                work.fail(failed, e);
+                continue
+            }
 
-            let val = work.backtrace(endpoint)?;
-            Ok(terminal.extract(work, val))
-        } else { bail!("no solution (try adding more bagger plugins!)") }
+            // make next search layer
+            for t in &self.solver.transforms {
+                t.apply(work, node);
+            }
+        }
     }
 }
 
@@ -137,41 +612,327 @@ impl Solver {
 #[derive(Debug)]
 pub struct Solution {
     pub bag_expr: BagExpr,
+    /// The nodes visited from the `Request` to the terminal.
+    pub path: Vec<Step>,
+    /// The URIs of the requests joined on the way.
+    pub joined: Vec<Uri>,
+}
+
+impl Solution {
+    pub fn new(bag_expr: BagExpr) -> Solution {
+        Solution {
+            bag_expr,
+            path: Vec::new(),
+            joined: Vec::new(),
+        }
+    }
+
+    /// Describe the path taken, such as
+    /// `Request(a.txt) -> LocalPath(a.txt) -> Producer(Bag<str>)`.
+    pub fn describe(&self) -> String {
+        describe_path(&self.path)
+    }
+
+    /// The total cost of the path taken.
+    pub fn cost(&self) -> Cost {
+        self.path.last().map(|s| s.cost).unwrap_or_default()
+    }
+}
+
+fn describe_path(path: &[Step]) -> String {
+    let names: Vec<_> = path.iter().map(|s| s.node.as_str()).collect();
+    names.join(" -> ")
+}
+
+/// A node on the path to a solution, and the edge taken to reach it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// Description of the node, from `Node::describe`.
+    pub node: String,
+    /// Flags satisfied by the edge.
+    pub satisfies: Vec<String>,
+    /// Request arguments the edge uses.
+    pub args: Vec<String>,
+    /// Exploration priority of the edge.
+    pub priority: i32,
+    /// Cost of the path up to and including this step.
+    pub cost: Cost,
 }
 
 /// Data used during the resolution of a specific asset.
 pub struct Working {
+    /// The requested URI, before aliasing.
+    pub uri: Uri,
     pub args: FlagMap<String>,
+    /// Where bags loading at run time look for the asset, if not at its path.
+    pub runtime_path: Option<String>,
     nodes: Vec<Option<NodeInstance>>,
     new_nodes: Vec<NodeInstance>,
     queue: WorkingQueue,
+    weights: Weights,
     pub target: BagInfo,
     pub required: FlagSet,
     pub forbidden: FlagSet,
     pub span: Span,
+    /// How many joins deep this search is.
+    depth: usize,
+    /// Values kept by the index of their node, once evaluated.
+    kept: RefCell<HashMap<usize, Kept>>,
+    reach: Rc<Reach>,
+    /// A required flag that some path could not satisfy.
+    unmet: Option<Flag>,
+    /// Why the first stopped edge was stopped. Stopped nodes are kept for the
+    /// report, but never explored.
+    stopped: Option<String>,
+}
+
+/// A value kept once evaluated, so that other routes through its node do not
+/// evaluate it again. Only values that can be handed out more than once are
+/// kept: readers are buffered as they are read, for the next route to replay.
+/// Other values are moved into the edge that takes them.
+enum Kept {
+    Unit,
+    Read(Rc<RefCell<Buffered>>),
+}
+
+impl Kept {
+    fn new(value: Box<Any>) -> Result<Kept, Box<Any>> {
+        if value.is::<()>() { return Ok(Kept::Unit) }
+        let read = value.downcast::<Box<Read>>()?;
+        Ok(Kept::Read(Rc::new(RefCell::new(Buffered { read: *read, buf: Vec::new() }))))
+    }
+
+    fn value(&self) -> Box<Any> {
+        match *self {
+            Kept::Unit => Box::new(()),
+            Kept::Read(ref source) => {
+                let replay = Replay { source: source.clone(), pos: 0 };
+                Box::new(Box::new(replay) as Box<Read>)
+            },
+        }
+    }
+}
+
+struct Buffered {
+    read: Box<Read>,
+    /// Everything read so far.
+    buf: Vec<u8>,
+}
+
+/// Reads a buffered reader from the start.
+struct Replay {
+    source: Rc<RefCell<Buffered>>,
+    pos: usize,
+}
+
+impl Read for Replay {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut source = self.source.borrow_mut();
+        let source = &mut *source;
+        if self.pos == source.buf.len() {
+            let mut chunk = [0; 8192];
+            let len = cmp::min(out.len(), chunk.len());
+            let read = source.read.read(&mut chunk[..len])?;
+            source.buf.extend_from_slice(&chunk[..read]);
+        }
+        let len = cmp::min(out.len(), source.buf.len() - self.pos);
+        out[..len].copy_from_slice(&source.buf[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
 }
 
 impl Working {
-    fn backtrace(&mut self, from: usize) -> Result<Box<Any>, Error> {
-        if from == 0 { return Ok(Box::new(()) as _) }
-        if let Some(n) = self.nodes[from].take() {
-            (n.value?)(self.backtrace(n.parent)?)
-        } else {
-            bail!("backtrace loop")
+    /// Copy the request and the nodes explored so far, but not their values,
+    /// so that the search can go on after handing a copy to a terminal.
+    fn snapshot(&self) -> Working {
+        let nodes = self.nodes.iter()
+            .map(|n| n.as_ref().map(NodeInstance::snapshot))
+            .collect();
+        Working {
+            uri: self.uri.clone(),
+            args: self.args.clone(),
+            runtime_path: self.runtime_path.clone(),
+            nodes,
+            new_nodes: Vec::new(),
+            queue: WorkingQueue::new(),
+            weights: self.weights,
+            target: self.target.clone(),
+            required: self.required.clone(),
+            forbidden: self.forbidden.clone(),
+            span: self.span,
+            depth: self.depth,
+            kept: RefCell::new(HashMap::new()),
+            reach: self.reach.clone(),
+            unmet: self.unmet,
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Report every node explored so far.
+    pub fn graph(&self) -> SearchGraph {
+        let nodes = self.nodes.iter()
+            .enumerate()
+            .filter_map(|(index, n)| n.as_ref().map(|n| (index, n)))
+            .map(|(index, n)| {
+                let mut satisfies: Vec<_> = n.satisfies.iter()
+                    .map(Flag::name)
+                    .collect();
+                satisfies.sort();
+                SearchNode {
+                    index,
+                    parent: if index == 0 { None } else { Some(n.parent) },
+                    name: n.name.clone(),
+                    satisfies,
+                    priority: n.priority,
+                    cost: n.cost,
+                    stop: n.value.as_ref().err()
+                        .map(ToString::to_string)
+                        .or_else(|| n.rejected.clone()),
+                    duplicate: n.duplicate,
+                    joined: n.joined.iter()
+                        .map(|&(ref w, end)| describe_path(&w.path(end)))
+                        .collect(),
+                }
+            })
+            .collect();
+        SearchGraph { nodes, end: None }
+    }
+
+    /// The steps from the request to a node.
+    fn path(&self, to: usize) -> Vec<Step> {
+        let mut path = Vec::new();
+        let mut next = to;
+        loop {
+            let node = get_node(&self.nodes, next);
+            let mut satisfies: Vec<_> = node.edge_satisfies.iter()
+                .map(Flag::name)
+                .collect();
+            satisfies.sort();
+            let mut args: Vec<_> = node.edge_args.iter()
+                .map(Flag::name)
+                .collect();
+            args.sort();
+            path.push(Step {
+                node: node.name.clone(),
+                satisfies,
+                args,
+                priority: node.priority,
+                cost: node.cost,
+            });
+            if next == 0 { break }
+            next = node.parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The URIs of the requests joined on the path to a node, including
+    /// those joined by the joined requests.
+    fn joined_uris(&self, to: usize) -> Vec<Uri> {
+        let mut uris = Vec::new();
+        let mut next = to;
+        loop {
+            let node = get_node(&self.nodes, next);
+            for &(ref work, end) in &node.joined {
+                uris.push(work.uri.clone());
+                uris.extend(work.joined_uris(end));
+            }
+            if next == 0 { break }
+            next = node.parent;
+        }
+        uris
+    }
+
+    /// Evaluate the values along the path to a node, starting from the last
+    /// one kept. On failure, returns the node whose value failed.
+    fn eval(&self, to: usize) -> Result<Box<Any>, (usize, Error)> {
+        if to == 0 { return Ok(Box::new(()) as _) }
+        if let Some(kept) = self.kept.borrow().get(&to) { return Ok(kept.value()) }
+
+        let node = get_node(&self.nodes, to);
+        let input = self.eval(node.parent)?;
+        let mut values = Vec::new();
+        for &(ref work, end) in &node.joined {
+            values.push(Some(work.eval(end).map_err(|(_, e)| (to, e))?));
+        }
+        let value = match node.value {
+            Ok(ref f) => f(input, Inputs { values }).map_err(|e| (to, e))?,
+            Err(ref e) => return Err((to, format_err!("{}", e))),
+        };
+        match Kept::new(value) {
+            Ok(kept) => {
+                let value = kept.value();
+                self.kept.borrow_mut().insert(to, kept);
+                Ok(value)
+            },
+            Err(value) => Ok(value),
+        }
+    }
+
+    /// Is there a node on the path to this one whose value failed?
+    fn is_failed(&self, mut index: usize) -> bool {
+        loop {
+            let node = get_node(&self.nodes, index);
+            if node.failed { return true }
+            if index == 0 { return false }
+            index = node.parent;
+        }
+    }
+
+    /// Mark a node whose value failed, so that nothing is built on it.
+    /// Nodes skipped in favor of an equivalent node that is now failed are
+    /// explored after all.
+    fn fail(&mut self, index: usize, err: Error) {
+        {
+            let node = self.nodes[index].as_mut().expect("failed node missing");
+            node.failed = true;
+            node.rejected = Some(err.to_string());
+        }
+
+        // nodes come after their parents, so one pass finds every failed path
+        let mut failed = vec![false; self.nodes.len()];
+        for i in 0..self.nodes.len() {
+            let node = get_node(&self.nodes, i);
+            failed[i] = node.failed || (i != 0 && failed[node.parent]);
+        }
+        let revived: Vec<_> = (0..self.nodes.len())
+            .filter(|&i| get_node(&self.nodes, i).duplicate
+                .map(|d| failed[d])
+                .unwrap_or(false))
+            .collect();
+        for i in revived {
+            let node = self.nodes[i].as_mut().unwrap();
+            node.duplicate = None;
+            self.queue.push((EdgeOrder {
+                active: true,
+                cost: self.weights.weigh(node.cost),
+                priority: node.priority,
+            }, Reverse(i)));
         }
     }
 }
 
-type WorkingQueue = BTreeMap<EdgeOrder, VecDeque<usize>>;
+/// Nodes to explore. Nodes of equal order are explored in the order they
+/// were created.
+type WorkingQueue = BinaryHeap<(EdgeOrder, Reverse<usize>)>;
 
 /// A dynamically typed transformation generator object.
 pub trait TransformInstance: 'static {
     fn apply(&self, working: &mut Working, node: usize);
+
+    /// The type of node the transform applies to, if there is just one.
+    fn input(&self) -> Option<TypeId> { None }
+
+    /// Every kind of edge the transform may add, if declared. Branches are
+    /// only pruned past types whose transforms all declare their edges.
+    fn edges(&self) -> Option<&[EdgeKind]> { None }
 }
 
 /// Transform instance from a closure.
 pub struct FnTransform<N, F> {
     func: F,
+    edges: Option<Vec<EdgeKind>>,
     _ph: PhantomData<N>,
 }
 
@@ -179,6 +940,17 @@ impl<N, F> FnTransform<N, F> {
     pub fn new(func: F) -> FnTransform<N, F> {
         FnTransform {
             func,
+            edges: None,
+            _ph: PhantomData,
+        }
+    }
+
+    /// A transform which only adds the given kinds of edges. Any other edge
+    /// it adds is stopped.
+    pub fn declared(func: F, edges: Vec<EdgeKind>) -> FnTransform<N, F> {
+        FnTransform {
+            func,
+            edges: Some(edges),
             _ph: PhantomData,
         }
     }
@@ -194,15 +966,22 @@ impl<N, F> TransformInstance for FnTransform<N, F>
         };
         
         let node = NodeInput::<N> {
+            uri: &working.uri,
             span: working.span,
             args: &working.args,
+            runtime_path: working.runtime_path.as_ref().map(String::as_str),
             node: data,
             edges: Edges {
                 nodes: &working.nodes,
                 new_nodes: &mut working.new_nodes,
                 queue: &mut working.queue,
+                weights: &working.weights,
                 required: &working.required,
                 forbidden: &working.forbidden,
+                reach: &working.reach,
+                unmet: &mut working.unmet,
+                stopped: &mut working.stopped,
+                declared: self.edges.as_ref().map(Vec::as_slice),
                 parent: index,
                 _ph: PhantomData,
             },
@@ -210,12 +989,24 @@ impl<N, F> TransformInstance for FnTransform<N, F>
 
         (self.func)(node)
     }
+
+    fn input(&self) -> Option<TypeId> {
+        Some(TypeId::of::<N>())
+    }
+
+    fn edges(&self) -> Option<&[EdgeKind]> {
+        self.edges.as_ref().map(Vec::as_slice)
+    }
 }
 
 /// Input node data.
 pub struct NodeInput<'work, N: Node> {
+    /// The requested URI, before aliasing.
+    pub uri: &'work Uri,
     pub span: Span,
     pub args: &'work FlagMap<String>,
+    /// Where bags loading at run time look for the asset, if not at its path.
+    pub runtime_path: Option<&'work str>,
     pub node: &'work N,
     pub edges: Edges<'work, N>,
 }
@@ -224,6 +1015,11 @@ impl<'work, N: Node> NodeInput<'work, N> {
     pub fn arg(&self, name: &str) -> Option<&str> {
         self.args.get(&Flag::from_str(name)).map(|v| v.as_str())
     }
+
+    /// Does the request require the flag?
+    pub fn requires(&self, name: &str) -> bool {
+        self.edges.required.contains(&Flag::from_str(name))
+    }
 }
 
 /// Manage transformations on a node.
@@ -231,8 +1027,14 @@ pub struct Edges<'work, N: Node> {
     nodes: &'work [Option<NodeInstance>],
     new_nodes: &'work mut Vec<NodeInstance>,
     queue: &'work mut WorkingQueue,
+    weights: &'work Weights,
     required: &'work FlagSet,
     forbidden: &'work FlagSet,
+    reach: &'work Reach,
+    unmet: &'work mut Option<Flag>,
+    stopped: &'work mut Option<String>,
+    /// The edges the transform declared, if it did.
+    declared: Option<&'work [EdgeKind]>,
     parent: usize,
     _ph: PhantomData<N>,
 }
@@ -245,13 +1047,67 @@ impl<'work, N: Node> Edges<'work, N> {
 }
 
 pub struct NodeInstance {
+    /// Description of the node, from `Node::describe`.
+    pub name: String,
+    /// Type and `Node::key` of the node, identifying equivalent nodes.
+    kind: TypeId,
+    key: Option<NodeKey>,
     data: Box<Any>,
     parent: usize,
+    /// Is there a stopped edge on the path to this node?
+    pub dead: bool,
+    /// Did the value of this node fail?
+    pub failed: bool,
+    /// An equivalent node that was expanded instead of this one.
+    pub duplicate: Option<usize>,
+    /// Why the node was not accepted as a solution, after it was reached.
+    pub rejected: Option<String>,
+    /// Required flags satisfied by the path to this node.
     pub satisfies: FlagSet,
-    value: Result<Box<Fn(Box<Any>) -> Result<Box<Any>, Error>>, Error>,
+    /// Flags satisfied by the edge to this node.
+    pub edge_satisfies: FlagSet,
+    /// Request arguments used by the edge to this node.
+    pub edge_args: FlagSet,
+    /// Exploration priority of the edge to this node.
+    pub priority: i32,
+    /// Cost of the path to this node, including the paths to joined nodes.
+    pub cost: Cost,
+    /// Nodes to join, from other requests, before this node is explored.
+    joins: Vec<Join>,
+    /// The searches that reached each joined node.
+    joined: Vec<(Working, usize)>,
+    value: Result<Box<Fn(Box<Any>, Inputs) -> Result<Box<Any>, Error>>, Error>,
 }
 
 impl NodeInstance {
+    /// Copy everything reported about the node, for `Working::snapshot`.
+    fn snapshot(&self) -> NodeInstance {
+        NodeInstance {
+            name: self.name.clone(),
+            kind: self.kind,
+            key: None,
+            data: Box::new(()),
+            parent: self.parent,
+            dead: self.dead,
+            failed: self.failed,
+            duplicate: self.duplicate,
+            rejected: self.rejected.clone(),
+            satisfies: self.satisfies.clone(),
+            edge_satisfies: self.edge_satisfies.clone(),
+            edge_args: self.edge_args.clone(),
+            priority: self.priority,
+            cost: self.cost,
+            joins: Vec::new(),
+            joined: self.joined.iter()
+                .map(|&(ref work, end)| (work.snapshot(), end))
+                .collect(),
+            value: match self.value {
+                Ok(_) => Ok(Box::new(default_val) as _),
+                Err(ref e) => Err(format_err!("{}", e)),
+            },
+        }
+    }
+
     pub fn downcast_ref<N: Node>(&self) -> Option<&N> {
         self.data.downcast_ref::<N>()
     }
@@ -273,9 +1129,12 @@ pub trait Terminal {
 /// Builds an edge between two nodes.
 pub struct EdgeBuilder<A: Node, B: Node> {
     priority: i32,
+    cost: Cost,
     satis: FlagSet,
+    args: FlagSet,
     stops: Option<Error>,
-    value: Option<Box<Fn(Box<Any>) -> Result<Box<Any>, Error>>>,
+    joins: Vec<Join>,
+    value: Option<Box<Fn(Box<Any>, Inputs) -> Result<Box<Any>, Error>>>,
     _ph: PhantomData<(A, B)>,
 }
 
@@ -284,8 +1143,11 @@ impl<A: Node, B: Node> EdgeBuilder<A, B> {
     pub fn new() -> EdgeBuilder<A, B> {
         EdgeBuilder {
             priority: 0,
+            cost: Cost::zero(),
             satis: FlagSet::new(),
+            args: FlagSet::new(),
             stops: None,
+            joins: Vec::new(),
             value: None,
             _ph: PhantomData,
         }
@@ -299,18 +1161,52 @@ impl<A: Node, B: Node> EdgeBuilder<A, B> {
     pub fn value<F>(&mut self, eval: F)
         where F: Fn(A::Target) -> Result<B::Target, Error> + 'static
     {
-        self.value = Some(Box::new(move |input|
+        self.value_joined(move |input, _| eval(input))
+    }
+
+    /// Like `value`, but also given the values of the nodes this edge joins.
+    pub fn value_joined<F>(&mut self, eval: F)
+        where F: Fn(A::Target, Inputs) -> Result<B::Target, Error> + 'static
+    {
+        self.value = Some(Box::new(move |input, inputs|
             eval(*match input.downcast::<A::Target>() {
                 Ok(r) => r,
                 Err(_) => bail!("could not cast edge")
-            }).map(|node| Box::new(node) as Box<Any>)
+            }, inputs).map(|node| Box::new(node) as Box<Any>)
         ))
     }
 
+    /// Also take the value of an `N` node, reached by solving a request for
+    /// another URI. The request keeps the forbidden flags of this one, and
+    /// is configured and aliased like any other request. Returns the index
+    /// of the value in the `Inputs` given to `value_joined`.
+    pub fn join<N: Node>(&mut self, uri: Uri) -> usize {
+        self.joins.push(Join {
+            uri,
+            kind: TypeId::of::<N>(),
+            name: nodes::short_type_name::<N>(),
+        });
+        self.joins.len() - 1
+    }
+
+    /// Break ties between paths of equal cost. Higher priority edges are
+    /// explored first.
     pub fn priority(&mut self, priority: i32) {
         self.priority = priority
     }
 
+    /// Set the cost of taking this edge. See the `cost` module for how
+    /// costs are compared.
+    pub fn cost(&mut self, cost: Cost) {
+        self.cost = cost
+    }
+
+    /// Record that the edge uses a request argument, for
+    /// `Bagger::check_solution`.
+    pub fn uses_arg(&mut self, arg: &str) {
+        self.args.insert(Flag::from_str(arg));
+    }
+
     pub fn satisfies_flag(&mut self, flag: Flag) {
         self.satis.insert(flag);
     }
@@ -330,44 +1226,71 @@ impl<A: Node, B: Node> EdgeBuilder<A, B> {
             .next()
         { self.stop(format_err!("no solution without flag \"{}\"", f)) }
 
+        // stop if the transform did not declare this edge, since the search
+        // relies on declarations to prune
+        if let Some(declared) = es.declared {
+            let kind = TypeId::of::<B>();
+            if !declared.iter().any(|e| e.kind == kind && self.satis.is_subset(&e.flags)) {
+                self.stop(format_err!("edge to {} not declared by its transform", n.describe()))
+            }
+        }
+
         // some primitive inputs
         let parent = es.parent;
         let stopped = self.stops.is_some();
 
         // value function
         let value = match (self.value, self.stops) {
-            (_, Some(e)) => Err(e),
+            (_, Some(e)) => {
+                es.stopped.get_or_insert_with(|| e.to_string());
+                Err(e)
+            },
             (Some(f), _) => Ok(f),
             (None, _) => Ok(Box::new(default_val) as _),
         };
 
-        // build true set of satisfied flags
-        let satisfies = {
+        // build true set of satisfied flags, total cost, and liveness
+        let (satisfies, cost, dead) = {
             let parent = &get_node(&es.nodes, parent);
             let mut all = parent.satisfies.clone();
             all.extend(self.satis.intersection(&es.required));
-            all
+            (all, parent.cost + self.cost, parent.dead || stopped)
         };
-        let satis_count = satisfies.len() as u32;
+
+        // prune the node if it can no longer satisfy the required flags
+        let unreachable = es.reach.missing(TypeId::of::<B>(), &satisfies, es.required);
+        if let Some(flag) = unreachable { es.unmet.get_or_insert(flag); }
 
         // create new node instace
         let index = es.nodes.len() + es.new_nodes.len();
         let node = NodeInstance {
+            name: n.describe(),
+            kind: TypeId::of::<B>(),
+            key: n.key(),
             data: Box::new(n),
             parent,
+            dead,
+            failed: false,
+            duplicate: None,
+            rejected: unreachable.map(unreachable_message),
             satisfies,
+            edge_satisfies: self.satis,
+            edge_args: self.args,
+            priority: self.priority,
+            cost,
+            joins: self.joins,
+            joined: Vec::new(),
             value,
         };
         es.new_nodes.push(node);
 
-        // add to explore queue
-        es.queue
-            .entry(EdgeOrder {
-                active: !stopped,
-                satisfies: satis_count,
-                priority: self.priority,
-            })
-            .or_insert_with(|| VecDeque::new())
-            .push_back(index);
+        // add to explore queue. Dead and pruned nodes are only kept for the
+        // report, so they never count against the budget
+        if dead || unreachable.is_some() { return }
+        es.queue.push((EdgeOrder {
+            active: true,
+            cost: es.weights.weigh(cost),
+            priority: self.priority,
+        }, Reverse(index)));
     }
 }
@@ -0,0 +1,131 @@
+extern crate bagger;
+
+mod common;
+
+use common::fixture;
+use bagger::{Bagger, Config, BagRequest, BagInfo, Uri};
+use bagger::check::{Diagnostic, DiagnosticKind, Severity};
+
+use std::str::FromStr;
+
+fn find<'a>(diags: &'a [Diagnostic], kind: DiagnosticKind, text: &str) -> &'a Diagnostic {
+    diags.iter()
+        .find(|d| d.kind == kind && d.message.contains(text))
+        .unwrap_or_else(|| panic!("no {:?} diagnostic for {:?} in {:#?}", kind, text, diags))
+}
+
+const MANIFEST: &str = r#"[defaults]
+static = true
+statik = true
+content = "text/plain"
+colour = "blue"
+
+[alias]
+"logo.png" = "images/logo.png"
+"missing/*" = "nothing/*"
+
+[target."cfg(unix"]
+include = true
+
+[format."Vec<u8"]
+loader = "my loader"
+
+[sparkles]
+shiny = true
+"#;
+
+#[test]
+fn check_manifests() {
+    let dir = fixture("check", &[("Bag.toml", MANIFEST), ("images/logo.png", "")]);
+    let diags = Bagger::new().check_manifests(&dir);
+    assert_eq!(diags.len(), 6, "{:#?}", diags);
+
+    let expect = [
+        (DiagnosticKind::UnknownFlag, "\"statik\"", 3, Severity::Warning),
+        (DiagnosticKind::UnknownArg, "\"colour\"", 5, Severity::Warning),
+        (DiagnosticKind::InvalidValue, "\"cfg(unix\"", 11, Severity::Error),
+        (DiagnosticKind::InvalidValue, "\"Vec<u8\"", 14, Severity::Error),
+        (DiagnosticKind::InvalidLoader, "\"my loader\"", 15, Severity::Error),
+        (DiagnosticKind::UnknownKey, "\"sparkles\"", 17, Severity::Error),
+    ];
+    for &(kind, text, line, severity) in &expect {
+        let diag = find(&diags, kind, text);
+        assert_eq!(diag.file, Some(dir.join("Bag.toml")));
+        assert_eq!(diag.line, Some(line), "{}", diag);
+        assert_eq!(diag.severity, severity);
+    }
+}
+
+#[test]
+fn check_alias_files() {
+    let dir = fixture("alias", &[
+        ("Bag.toml", "[alias]\n\"logo.png\" = \"images/logo.png\"\n\"missing/*\" = \"nothing/*\"\n"),
+        ("images/logo.png", ""),
+    ]);
+    let diags = Bagger::with_config(Config::load(&dir).unwrap()).unwrap().check_config();
+    assert_eq!(diags.len(), 1, "{:#?}", diags);
+    let diag = find(&diags, DiagnosticKind::UnmatchedAlias, "\"missing/*\"");
+    assert_eq!(diag.file, Some(dir.join("Bag.toml")));
+    assert_eq!(diag.line, Some(3));
+    assert!(!diag.is_error());
+}
+
+#[test]
+fn check_syntax() {
+    let dir = fixture("broken", &[("Bag.toml", "[defaults\nstatic = true\n")]);
+    let diags = Bagger::new().check_manifests(&dir);
+    assert_eq!(diags.len(), 1, "{:#?}", diags);
+    assert_eq!(diags[0].kind, DiagnosticKind::Syntax);
+    assert!(diags[0].is_error());
+}
+
+#[test]
+fn check_config() {
+    let config = Config::parse(r#"
+        [defaults]
+        static = true
+
+        [profile.dev]
+        include = false
+        mipmaps = true
+
+        [profile.ci]
+        static = false
+    "#).unwrap();
+    let diags = Bagger::with_config(config).unwrap().check_config();
+    assert_eq!(diags.len(), 2, "{:#?}", diags);
+    let diag = find(&diags, DiagnosticKind::UnknownFlag, "\"mipmaps\"");
+    assert_eq!(diag.file, None);
+    assert_eq!(diag.to_string(), "warning: unknown flag \"mipmaps\"");
+    // custom profiles can be forwarded by a build script
+    assert!(!find(&diags, DiagnosticKind::UnknownProfile, "\"ci\"").is_error());
+}
+
+#[test]
+fn check_declared() {
+    let mut bggr = Bagger::with_config(Config::parse(r#"
+        [defaults]
+        reload = true
+        watch = "1s"
+    "#).unwrap()).unwrap();
+    bggr.declare_flag("reload");
+    bggr.declare_arg("watch");
+    assert_eq!(bggr.check_config(), vec![]);
+}
+
+#[test]
+fn check_request() {
+    let mut req = BagRequest::new(
+        Uri::from_str("hello.txt").unwrap(),
+        BagInfo::empty(),
+    );
+    req.require("static");
+    req.require("statik");
+    req.forbid("stuff");
+
+    let diags = Bagger::new().check_request(&req);
+    assert_eq!(diags.len(), 2, "{:#?}", diags);
+    // undeclared flags may still be satisfied by some plugin
+    assert!(!find(&diags, DiagnosticKind::UnknownFlag, "\"statik\"").is_error());
+    assert!(!find(&diags, DiagnosticKind::UnknownFlag, "\"stuff\"").is_error());
+}
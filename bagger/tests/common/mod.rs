@@ -0,0 +1,47 @@
+//! Helpers shared by the integration tests.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static FIXTURES: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of files written for one test, removed when dropped.
+pub struct Fixture(PathBuf);
+
+impl Deref for Fixture {
+    type Target = Path;
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl AsRef<Path> for Fixture {
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write files into a fresh directory for one test. The directory is named
+/// after the test and the process, so parallel runs do not share it.
+pub fn fixture(name: &str, files: &[(&str, &str)]) -> Fixture {
+    let dir = env::temp_dir().join(format!(
+        "bagger-{}-{}-{}",
+        name,
+        process::id(),
+        FIXTURES.fetch_add(1, Ordering::SeqCst),
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for &(path, text) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    Fixture(fs::canonicalize(dir).unwrap())
+}
@@ -0,0 +1,347 @@
+#[macro_use]
+extern crate syn;
+#[macro_use]
+extern crate quote;
+extern crate bagger;
+
+mod common;
+
+use common::fixture;
+use bagger::{Config, Build, BagRequest, BagInfo, Uri, Flag};
+use bagger::config::{Setting, watched_paths};
+use bagger::cfg::{Cfg, CfgSet};
+
+use std::fs;
+use std::str::FromStr;
+use std::path::Path;
+
+const README: &str = r#"
+[alias]
+"image/**" = "public/img/**"
+
+[runtime.alias]
+"image/**" = "assets/**"
+
+[target."cfg(target_arch = \"wasm32\")"]
+static = true
+
+[asset."LICENSE"]
+static = true
+
+[format."image::RgbaImage"]
+loader = "bag_image::LoadImageBuffer"
+"#;
+
+fn build(triple: &str, profile: &str) -> Build {
+    Build {
+        cfg: Some(CfgSet::from_triple(triple)),
+        profile: Some(profile.to_owned()),
+    }
+}
+
+fn request(uri: &str) -> BagRequest {
+    BagRequest::new(
+        Uri::from_str(uri).unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap())
+}
+
+#[test]
+pub fn parse_readme() {
+    let config = Config::parse(README).unwrap();
+    assert_eq!(config.alias["image/**"], "public/img/**");
+    assert_eq!(config.runtime.alias["image/**"], "assets/**");
+    assert_eq!(config.target["cfg(target_arch = \"wasm32\")"].0["static"], Setting::Flag(true));
+    assert_eq!(config.asset["LICENSE"].0["static"], Setting::Flag(true));
+    assert_eq!(config.format["image::RgbaImage"].loader, "bag_image::LoadImageBuffer");
+}
+
+#[test]
+pub fn parse_empty() {
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+}
+
+#[test]
+pub fn apply_defaults() {
+    let config = Config::parse(r#"
+        [defaults]
+        static = true
+        include = false
+        content = "text/plain"
+    "#).unwrap();
+
+    let mut req = request("./tests/hello.txt");
+    config.apply(&mut req, &Build::new()).unwrap();
+    assert!(req.required.contains(&Flag::from_str("static")));
+    assert!(req.forbidden.contains(&Flag::from_str("include")));
+    assert_eq!(req.args[&Flag::from_str("content")], "text/plain");
+
+    // explicit settings win
+    let mut req = request("./tests/hello.txt");
+    req.forbid("static");
+    req.require("include");
+    req.arg("content", "text/html");
+    config.apply(&mut req, &Build::new()).unwrap();
+    assert!(req.forbidden.contains(&Flag::from_str("static")));
+    assert!(req.required.contains(&Flag::from_str("include")));
+    assert_eq!(req.args[&Flag::from_str("content")], "text/html");
+}
+
+#[test]
+pub fn resolve_alias() {
+    let config = Config::parse(r#"
+        [alias]
+        "text/**" = "tests/**"
+        "text/greeting.txt" = "tests/hello.txt"
+        "missing/**" = "tests/missing/**"
+    "#).unwrap();
+
+    let uri = Uri::from_str("text/hello.txt").unwrap();
+    assert_eq!(config.resolve_alias(&uri).unwrap().path, Path::new("tests/hello.txt"));
+
+    let uri = Uri::from_str("text/greeting.txt").unwrap();
+    assert_eq!(config.resolve_alias(&uri).unwrap().path, Path::new("tests/hello.txt"));
+
+    let uri = Uri::from_str("tests/hello.txt").unwrap();
+    assert_eq!(config.resolve_alias(&uri).unwrap().path, Path::new("tests/hello.txt"));
+
+    let uri = Uri::from_str("missing/hello.txt").unwrap();
+    let err = config.resolve_alias(&uri).unwrap_err().to_string();
+    assert!(err.contains("missing file \"tests/missing/hello.txt\""), "{}", err);
+}
+
+#[test]
+pub fn eval_cfg() {
+    let wasm = CfgSet::from_triple("wasm32-unknown-unknown");
+    let linux = CfgSet::from_triple("x86_64-unknown-linux-gnu");
+
+    let pred = Cfg::from_str(r#"cfg(target_arch = "wasm32")"#).unwrap();
+    assert!(pred.eval(&wasm));
+    assert!(!pred.eval(&linux));
+
+    let pred = Cfg::from_str(r#"cfg(all(unix, not(target_env = "musl")))"#).unwrap();
+    assert!(!pred.eval(&wasm));
+    assert!(pred.eval(&linux));
+
+    let pred = Cfg::from_str(r#"cfg(any(windows, target_family = "wasm"))"#).unwrap();
+    assert!(pred.eval(&wasm));
+    assert!(!pred.eval(&linux));
+
+    let pred = Cfg::from_str("wasm32-unknown-unknown").unwrap();
+    assert!(pred.eval(&wasm));
+    assert!(!pred.eval(&linux));
+
+    assert!(Cfg::from_str("cfg(all(unix)").is_err());
+    assert!(Cfg::from_str("cfg(not(unix, windows))").is_err());
+}
+
+#[test]
+pub fn apply_runtime_path() {
+    let config = Config::parse(r#"
+        [runtime.alias]
+        "tests/**" = "assets/**"
+    "#).unwrap();
+
+    let mut req = request("tests/hello.txt");
+    config.apply(&mut req, &Build::new()).unwrap();
+    assert_eq!(req.runtime_path, Some("assets/hello.txt".to_owned()));
+    // it is not a user argument
+    assert!(req.args.is_empty());
+
+    // explicit settings win
+    let mut req = request("tests/hello.txt");
+    req.runtime_path = Some("elsewhere/hello.txt".to_owned());
+    config.apply(&mut req, &Build::new()).unwrap();
+    assert_eq!(req.runtime_path, Some("elsewhere/hello.txt".to_owned()));
+}
+
+#[test]
+pub fn apply_target() {
+    let config = Config::parse(r#"
+        [defaults]
+        static = false
+
+        [target."cfg(target_arch = \"wasm32\")"]
+        static = true
+    "#).unwrap();
+    let wasm = build("wasm32-unknown-unknown", "dev");
+    let linux = build("x86_64-unknown-linux-gnu", "dev");
+    let static_flag = Flag::from_str("static");
+
+    let mut req = request("./tests/hello.txt");
+    config.apply(&mut req, &wasm).unwrap();
+    assert!(req.required.contains(&static_flag));
+
+    let mut req = request("./tests/hello.txt");
+    config.apply(&mut req, &linux).unwrap();
+    assert!(req.forbidden.contains(&static_flag));
+
+    // explicit settings win
+    let mut req = request("./tests/hello.txt");
+    req.forbid("static");
+    config.apply(&mut req, &wasm).unwrap();
+    assert!(req.forbidden.contains(&static_flag));
+
+    // not(...) must not match a target that is unknown
+    let mut req = request("./tests/hello.txt");
+    let err = config.apply(&mut req, &Build::new()).unwrap_err();
+    assert!(err.to_string().contains("cargo:rustc-env=TARGET"), "{}", err);
+    assert!(!req.required.contains(&static_flag));
+}
+
+#[test]
+pub fn apply_asset() {
+    let config = Config::parse(r#"
+        [defaults]
+        static = false
+        content = "application/octet-stream"
+
+        [target."cfg(unix)"]
+        include = false
+        content = "text/html"
+
+        [asset."tests/**"]
+        static = true
+        content = "text/markdown"
+
+        [asset."tests/*.txt"]
+        content = "text/plain"
+    "#).unwrap();
+    let linux = build("x86_64-unknown-linux-gnu", "dev");
+    let content = Flag::from_str("content");
+
+    let mut req = request("tests/hello.txt");
+    config.apply(&mut req, &linux).unwrap();
+    assert!(req.required.contains(&Flag::from_str("static")));
+    assert!(req.forbidden.contains(&Flag::from_str("include")));
+    assert_eq!(req.args[&content], "text/plain");
+
+    let mut req = request("tests/tiny.png");
+    config.apply(&mut req, &linux).unwrap();
+    assert_eq!(req.args[&content], "text/markdown");
+
+    let mut req = request("LICENSE");
+    config.apply(&mut req, &linux).unwrap();
+    assert!(req.forbidden.contains(&Flag::from_str("static")));
+    assert_eq!(req.args[&content], "text/html");
+
+    let mut req = request("LICENSE");
+    config.apply(&mut req, &build("wasm32-unknown-unknown", "dev")).unwrap();
+    assert_eq!(req.args[&content], "application/octet-stream");
+
+    // explicit settings win
+    let mut req = request("tests/hello.txt");
+    req.arg("content", "text/csv");
+    config.apply(&mut req, &linux).unwrap();
+    assert_eq!(req.args[&content], "text/csv");
+}
+
+#[test]
+pub fn apply_profile() {
+    let config = Config::parse(r#"
+        [defaults]
+        content = "text/plain"
+
+        [profile.dev]
+        static = false
+
+        [profile.release]
+        static = true
+        include = true
+
+        [target."cfg(target_arch = \"wasm32\")"]
+        static = true
+
+        [asset."LICENSE"]
+        include = false
+    "#).unwrap();
+    let static_flag = Flag::from_str("static");
+    let include_flag = Flag::from_str("include");
+
+    let mut req = request("tests/hello.txt");
+    config.apply(&mut req, &build("x86_64-unknown-linux-gnu", "dev")).unwrap();
+    assert!(req.forbidden.contains(&static_flag));
+    assert_eq!(req.args[&Flag::from_str("content")], "text/plain");
+
+    let mut req = request("tests/hello.txt");
+    config.apply(&mut req, &build("x86_64-unknown-linux-gnu", "release")).unwrap();
+    assert!(req.required.contains(&static_flag));
+    assert!(req.required.contains(&include_flag));
+
+    // target overrides profile
+    let mut req = request("tests/hello.txt");
+    config.apply(&mut req, &build("wasm32-unknown-unknown", "dev")).unwrap();
+    assert!(req.required.contains(&static_flag));
+
+    // asset overrides profile
+    let mut req = request("LICENSE");
+    config.apply(&mut req, &build("x86_64-unknown-linux-gnu", "release")).unwrap();
+    assert!(req.forbidden.contains(&include_flag));
+
+    // the profile is not guessed
+    let mut req = request("tests/hello.txt");
+    let unknown = Build { profile: None, ..build("x86_64-unknown-linux-gnu", "dev") };
+    let err = config.apply(&mut req, &unknown).unwrap_err();
+    assert!(err.to_string().contains("cargo:rustc-env=PROFILE"), "{}", err);
+}
+
+#[test]
+pub fn workspace_inheritance() {
+    let dir = fixture("workspace", &[
+        ("Cargo.toml", "[workspace]\nmembers = [\"member\"]\n"),
+        ("Bag.toml", r#"
+            [alias]
+            "image/**" = "public/img/**"
+            "text/**" = "public/text/**"
+
+            [profile.release]
+            static = true
+            include = true
+
+            [format."::image::RgbaImage"]
+            loader = "::bag_image::LoadImageBuffer"
+        "#),
+        ("member/Cargo.toml", "[package]\nname = \"member\"\nversion = \"0.0.0\"\n"),
+        ("member/Bag.toml", r#"
+            [alias]
+            "text/**" = "docs/**"
+
+            [profile.release]
+            include = false
+        "#),
+    ]);
+    let config = Config::load(dir.join("member")).unwrap();
+    assert_eq!(config.sources, vec![
+        dir.join("Bag.toml"),
+        dir.join("member/Bag.toml"),
+    ]);
+    assert_eq!(config.alias["image/**"], "public/img/**");
+    assert_eq!(config.alias["text/**"], "docs/**");
+    assert_eq!(config.profile["release"].0["static"], Setting::Flag(true));
+    assert_eq!(config.profile["release"].0["include"], Setting::Flag(false));
+    assert_eq!(config.format["::image::RgbaImage"].loader, "::bag_image::LoadImageBuffer");
+
+    // printed config parses back to the same thing
+    let reparsed = Config::parse(&config.to_string()).unwrap();
+    assert_eq!(reparsed.alias, config.alias);
+    assert_eq!(reparsed.profile, config.profile);
+    assert_eq!(reparsed.format, config.format);
+}
+
+#[test]
+pub fn no_workspace() {
+    // manifests above a crate outside any workspace are not used
+    let dir = fixture("no_workspace", &[
+        ("Bag.toml", "[defaults]\nstatic = true\n"),
+        ("crate/Cargo.toml", "[package]\nname = \"crate\"\nversion = \"0.0.0\"\n"),
+    ]);
+    let config = Config::load(dir.join("crate")).unwrap();
+    assert!(config.sources.is_empty());
+    assert_eq!(config, Config { root: dir.join("crate"), ..Config::default() });
+
+    // "." still looks at the directories above it, up to the workspace of
+    // this repository
+    let root = fs::canonicalize("..").unwrap();
+    let watched = watched_paths(".");
+    assert!(watched.contains(&root.join("Cargo.toml")), "{:#?}", watched);
+    assert_eq!(watched.last(), Some(&root.join("Cargo.toml")));
+}
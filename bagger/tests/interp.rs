@@ -0,0 +1,71 @@
+#[macro_use]
+extern crate syn;
+#[macro_use]
+extern crate quote;
+extern crate bagger;
+
+use bagger::{BagRequest, BagInfo, Uri, Flag};
+use bagger::interp::{interpolate, interpolate_with};
+
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Variables for the tests, so that they do not share the environment of the
+/// test process.
+fn lookup(name: &str) -> Option<String> {
+    match name {
+        "BAGGER_TEST_DIR" => Some("assets"),
+        "BAGGER_TEST_NAME" => Some("logo"),
+        "BAGGER_TEST_OUT" => Some("target/out"),
+        "BAGGER_TEST_MIME" => Some("text/plain"),
+        _ => None,
+    }.map(ToOwned::to_owned)
+}
+
+#[test]
+pub fn interpolate_vars() {
+    let mut used = BTreeSet::new();
+    assert_eq!(
+        interpolate_with("${BAGGER_TEST_DIR}/${BAGGER_TEST_NAME}.png", &mut used, lookup).unwrap(),
+        "assets/logo.png",
+    );
+    assert!(used.contains("BAGGER_TEST_DIR"));
+    assert!(used.contains("BAGGER_TEST_NAME"));
+
+    let mut used = BTreeSet::new();
+    assert_eq!(
+        interpolate_with("$${BAGGER_TEST_DIR} $5", &mut used, lookup).unwrap(),
+        "${BAGGER_TEST_DIR} $5",
+    );
+    assert!(used.is_empty());
+}
+
+#[test]
+pub fn interpolate_errors() {
+    let mut used = BTreeSet::new();
+    let err = interpolate_with("${BAGGER_TEST_UNSET}/x", &mut used, lookup).unwrap_err();
+    assert!(err.to_string().contains("\"BAGGER_TEST_UNSET\""));
+    assert!(interpolate_with("${BAGGER_TEST_DIR", &mut used, lookup).is_err());
+    assert!(interpolate_with("${}", &mut used, lookup).is_err());
+
+    // the environment is used by default
+    let err = interpolate("${BAGGER_TEST_UNSET}/x", &mut used).unwrap_err();
+    assert!(err.to_string().contains("\"BAGGER_TEST_UNSET\""));
+}
+
+#[test]
+pub fn interpolate_request() {
+    let mut req = BagRequest::new(
+        Uri::from_str("${BAGGER_TEST_OUT}/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    req.arg("content", "${BAGGER_TEST_MIME}");
+    req.interpolate_with(lookup).unwrap();
+
+    assert_eq!(req.uri.path, Path::new("target/out/hello.txt"));
+    assert_eq!(req.args[&Flag::from_str("content")], "text/plain");
+    assert_eq!(
+        req.env.iter().map(String::as_str).collect::<Vec<_>>(),
+        vec!["BAGGER_TEST_MIME", "BAGGER_TEST_OUT"],
+    );
+}
@@ -3,10 +3,21 @@ extern crate syn;
 #[macro_use]
 extern crate quote;
 extern crate bagger;
+#[macro_use]
+extern crate failure;
+
+mod common;
 
-use bagger::{Bagger, BagRequest, Uri, BagInfo};
+use common::fixture;
+use bagger::{Bagger, BagRequest, Uri, BagInfo, Config, Cost, Weights};
+use bagger::{NodeInput, EdgeBuilder, Node};
+use bagger::expr::BagExpr;
+use bagger::nodes::{LocalPath, LocalRead, Producer, NodeKey, node_key};
+use bagger::report::NoSolution;
 
 use std::str::FromStr;
+use std::fs;
+use std::io::Read;
 
 #[test]
 pub fn solve_static_str() {
@@ -51,6 +62,261 @@ pub fn solve_include_str() {
     );
 }
 
+#[test]
+pub fn solution_path() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    req.require("include");
+
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.describe(),
+        "Request(./tests/hello.txt) -> LocalPath(./tests/hello.txt) \
+            -> Producer(AsyncBag<str> + Bag<str> + Unbag<&'static str> + Unbag<String>)",
+    );
+    assert_eq!(sol.path[1].satisfies, Vec::<String>::new());
+    assert_eq!(sol.path[2].satisfies, vec!["include", "static"]);
+    assert_eq!(sol.path[2].priority, 0);
+    assert_eq!(sol.path[2].cost, Cost::new(1, 1, 0));
+}
+
+#[test]
+pub fn solve_cheapest() {
+    let request = || BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+
+    // a plugin that is slow to compile, but cheaper than loading at run time
+    let mut bggr = Bagger::new();
+    bggr.transform(|mut n: NodeInput<LocalPath>| {
+        let mut edge = EdgeBuilder::new();
+        edge.cost(Cost::new(3, 0, 0));
+        edge.value(|_| Ok(BagExpr {
+            expr: quote! { slow_to_compile() },
+            returns: parse_quote!(SlowToCompile),
+        }));
+        let info = BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap();
+        n.edges.add(Producer(info), edge);
+    });
+    let sol = bggr.solve(request()).unwrap();
+    assert_eq!(sol.cost(), Cost::new(3, 0, 0));
+    assert_eq!(sol.bag_expr.expr, quote! { slow_to_compile() });
+
+    // unless compile time is what matters
+    bggr.set_weights(Weights { compile: 10, size: 1, runtime: 1 });
+    let sol = bggr.solve(request()).unwrap();
+    assert_eq!(sol.cost(), Cost::new(0, 0, 8));
+    assert_eq!(
+        sol.bag_expr.returns,
+        parse_quote!(::bag::ops::RuntimeFile<String>),
+    );
+}
+
+#[test]
+pub fn edge_order() {
+    use bagger::solver::EdgeOrder;
+
+    let order = |active, cost, priority| EdgeOrder { active, cost, priority };
+    // cheaper paths first, whatever their priority
+    assert!(order(true, 1, 0) > order(true, 2, 5));
+    // priority only breaks ties
+    assert!(order(true, 1, 5) > order(true, 1, 0));
+    // inactive routes last, however cheap
+    assert!(order(true, 9, 0) > order(false, 0, 9));
+}
+
+#[test]
+pub fn recover_from_failed_value() {
+    // a cheap route that fails once a terminal is reached
+    let mut bggr = Bagger::new();
+    bggr.declare_flag("decoded");
+    bggr.transform(|mut n: NodeInput<LocalPath>| {
+        let mut edge = EdgeBuilder::new();
+        edge.satisfies("decoded");
+        edge.value(|_| bail!("decode failed"));
+        let info = BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap();
+        n.edges.add(Producer(info), edge);
+    });
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+
+    let (sol, graph) = bggr.solve_with_graph(req);
+    let sol = sol.unwrap();
+    assert_eq!(sol.cost(), Cost::new(0, 0, 8));
+    assert!(graph.nodes.iter().any(|n| n.stop == Some("decode failed".to_string())));
+
+    // with no other route, the failure is the reason
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.require("decoded");
+    let err = bggr.solve(req).unwrap_err();
+    assert_eq!(err.downcast::<NoSolution>().unwrap().reason, "decode failed");
+}
+
+struct Opened;
+impl Node for Opened {
+    type Target = Box<Read>;
+}
+
+#[test]
+pub fn eval_values_once() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let opens = Arc::new(AtomicUsize::new(0));
+    let mut bggr = Bagger::new();
+    let counter = opens.clone();
+    bggr.transform(move |mut n: NodeInput<LocalPath>| {
+        let (path, counter) = (n.node.0.clone(), counter.clone());
+        let mut edge = EdgeBuilder::new();
+        edge.value(move |()| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(fs::File::open(&path)?) as _)
+        });
+        n.edges.add(Opened, edge);
+    });
+    bggr.transform(|mut n: NodeInput<Opened>| {
+        let info = || BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap();
+        // a cheap route that reads the file, then fails
+        let mut edge = EdgeBuilder::new();
+        edge.value(|mut read: Box<Read>| {
+            read.read_to_end(&mut Vec::new())?;
+            bail!("not utf-16")
+        });
+        n.edges.add(Producer(info()), edge);
+
+        let mut edge = EdgeBuilder::new();
+        edge.cost(Cost::new(1, 0, 0));
+        edge.value(|mut read: Box<Read>| {
+            let mut text = String::new();
+            read.read_to_string(&mut text)?;
+            Ok(BagExpr {
+                expr: quote! { text(#text) },
+                returns: parse_quote!(Text),
+            })
+        });
+        n.edges.add(Producer(info()), edge);
+    });
+
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    let sol = bggr.solve(req).unwrap();
+    // the second route reads the whole file, without opening it again
+    let text = fs::read_to_string("./tests/hello.txt").unwrap();
+    assert_eq!(sol.bag_expr.expr.to_string(), quote! { text(#text) }.to_string());
+    assert_eq!(opens.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+pub fn solve_all_ranked() {
+    // a plugin route that is never the cheapest
+    let mut bggr = Bagger::new();
+    bggr.transform(|mut n: NodeInput<LocalPath>| {
+        let mut edge = EdgeBuilder::new();
+        edge.cost(Cost::new(30, 0, 0));
+        edge.value(|_| Ok(BagExpr {
+            expr: quote! { slow_to_compile() },
+            returns: parse_quote!(SlowToCompile),
+        }));
+        let info = BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap();
+        n.edges.add(Producer(info), edge);
+    });
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+
+    let all: Vec<_> = bggr.solve_all(req).unwrap().collect();
+    let costs: Vec<_> = all.iter().map(|s| s.cost()).collect();
+    assert_eq!(costs, vec![Cost::new(1, 1, 0), Cost::new(3, 1, 0), Cost::new(30, 0, 0)]);
+    assert!(all[1].describe().contains("LocalRead"));
+    assert_eq!(all[2].bag_expr.expr, quote! { slow_to_compile() });
+}
+
+#[test]
+pub fn solve_cached() {
+    // relative paths are stamped against the crate, not the current directory
+    let dir = fixture("solve_cached", &[("hello.txt", "hello")]);
+    let request = || BagRequest::new(
+        Uri::from_str("hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+
+    let bggr = Bagger::with_config(Config::load(&dir).unwrap()).unwrap();
+    let first = bggr.solve_cached(request()).0.unwrap();
+    let second = bggr.solve_cached(request()).0.unwrap();
+    assert_eq!(bggr.cached(), 1);
+    assert_eq!(first.bag_expr.expr.to_string(), second.bag_expr.expr.to_string());
+    assert_eq!(first.bag_expr.returns, second.bag_expr.returns);
+    assert_eq!(first.path, second.path);
+
+    // a changed file is solved again
+    fs::write(dir.join("hello.txt"), "hello, world").unwrap();
+    bggr.solve_cached(request()).0.unwrap();
+    assert_eq!(bggr.cached(), 2);
+
+    bggr.clear_cache();
+    assert_eq!(bggr.cached(), 0);
+}
+
+/// Bag the lengths of a file and the file it joins.
+fn join_lengths(bggr: &mut Bagger, other: &'static str) {
+    bggr.transform(move |mut n: NodeInput<LocalRead>| {
+        let mut edge = EdgeBuilder::<LocalRead, Producer>::new();
+        let other = edge.join::<LocalRead>(Uri::from_str(other).unwrap());
+        edge.value_joined(move |mut read, mut inputs| {
+            let mut other_read = inputs.take::<LocalRead>(other)?;
+            let (mut a, mut b) = (Vec::new(), Vec::new());
+            read.read_to_end(&mut a)?;
+            other_read.read_to_end(&mut b)?;
+            let (a, b) = (a.len(), b.len());
+            Ok(BagExpr {
+                expr: quote! { lengths(#a, #b) },
+                returns: parse_quote!(Lengths),
+            })
+        });
+        let info = BagInfo::from_quote(parse_quote!(Bag<[usize]>)).unwrap();
+        n.edges.add(Producer(info), edge);
+    });
+}
+
+#[test]
+pub fn solve_join() {
+    let request = || BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<[usize]>)).unwrap());
+
+    let mut bggr = Bagger::new();
+    join_lengths(&mut bggr, "./tests/tiny.png");
+    let (sol, graph) = bggr.solve_with_graph(request());
+    let sol = sol.unwrap();
+    let a = fs::metadata("./tests/hello.txt").unwrap().len() as usize;
+    let b = fs::metadata("./tests/tiny.png").unwrap().len() as usize;
+    assert_eq!(sol.bag_expr.expr.to_string(), quote! { lengths(#a, #b) }.to_string());
+    assert!(graph.nodes.iter().any(|n| n.joined.iter().any(|p| p.contains("tiny.png"))));
+    // the joined path adds its cost
+    assert!(sol.cost().compile > 1);
+    assert_eq!(sol.joined, vec![Uri::from_str("./tests/tiny.png").unwrap()]);
+
+    let mut bggr = Bagger::new();
+    join_lengths(&mut bggr, "./tests/missing.png");
+    let err = bggr.solve(request()).unwrap_err();
+    assert!(err.to_string().starts_with("could not join LocalRead from \"./tests/missing.png\""));
+
+    // joined requests are aliased like any other
+    let config = Config::parse(r#"
+        [alias]
+        "img/**" = "tests/**"
+    "#).unwrap();
+    let mut bggr = Bagger::with_config(config).unwrap();
+    join_lengths(&mut bggr, "img/tiny.png");
+    let sol = bggr.solve(request()).unwrap();
+    assert_eq!(sol.bag_expr.expr.to_string(), quote! { lengths(#a, #b) }.to_string());
+}
+
 #[test]
 pub fn solve_include_bytes() {
     let bggr = Bagger::new();
@@ -107,3 +373,468 @@ pub fn solve_include_png_as_str() {
 
     assert!(bggr.solve(req).is_ok());
 }
+
+#[test]
+pub fn solve_aliased_include_str() {
+    let config = Config::parse(r#"
+        [alias]
+        "text/**" = "tests/**"
+    "#).unwrap();
+    let bggr = Bagger::with_config(config).unwrap();
+    let mut req = BagRequest::new(
+        Uri::from_str("text/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    req.require("include");
+
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! { ::bag::bags::Static::<&'static str>({ include_str!("tests/hello.txt") }) },
+    );
+}
+
+#[test]
+pub fn solve_runtime_str() {
+    let config = Config::parse(r#"
+        [runtime.alias]
+        "tests/**" = "assets/**"
+    "#).unwrap();
+    let bggr = Bagger::with_config(config).unwrap();
+    let mut req = BagRequest::new(
+        Uri::from_str("tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.forbid("static");
+
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! {
+            ::bag::ops::runtime_file::<String>(
+                ::bag::alias::RuntimePath::new("tests/hello.txt", "assets/hello.txt"))
+        },
+    );
+    assert_eq!(
+        sol.bag_expr.returns,
+        parse_quote!(::bag::ops::RuntimeFile<String>),
+    );
+}
+
+#[test]
+pub fn solve_async_str() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(AsyncBag<str>)).unwrap());
+    req.forbid("static");
+
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! {
+            ::bag::ops::async_runtime_file::<String>(
+                ::bag::alias::RuntimePath::new("tests/hello.txt", "tests/hello.txt"))
+        },
+    );
+    assert_eq!(
+        sol.bag_expr.returns,
+        parse_quote!(::bag::ops::AsyncRuntimeFile<String>),
+    );
+}
+
+#[test]
+pub fn solve_async_include_str() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(AsyncBag<str>)).unwrap());
+    req.require("include");
+
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! { ::bag::bags::Static::<&'static str>({ include_str!("./tests/hello.txt") }) },
+    );
+    assert_eq!(
+        sol.bag_expr.returns,
+        parse_quote!(::bag::bags::Static<&'static str>),
+    );
+}
+
+#[test]
+pub fn solve_retrying_str() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.forbid("static");
+    req.arg("retry", "5");
+    req.arg("retry_backoff_ms", "20");
+
+    let sol = bggr.solve(req.clone()).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! {
+            ::bag::ops::retrying_runtime_file::<String>(
+                ::bag::alias::RuntimePath::new("tests/hello.txt", "tests/hello.txt"),
+                ::bag::bags::RetryPolicy {
+                    max_attempts: 5usize,
+                    backoff: ::std::time::Duration::from_millis(20u64),
+                    ..::bag::bags::RetryPolicy::default()
+                })
+        },
+    );
+    assert_eq!(
+        sol.bag_expr.returns,
+        parse_quote!(::bag::ops::RetryingRuntimeFile<String>),
+    );
+    assert_eq!(bggr.check_solution(&req, &sol), vec![]);
+
+    // nor can bags included in the binary, so the argument is reported
+    let mut static_req = req.clone();
+    static_req.forbidden.clear();
+    static_req.target = BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap();
+    let sol = bggr.solve(static_req.clone()).unwrap();
+    let diags = bggr.check_solution(&static_req, &sol);
+    assert_eq!(diags.len(), 1, "{:#?}", diags);
+    assert_eq!(diags[0].kind, bagger::check::DiagnosticKind::IgnoredArg);
+    assert!(!diags[0].is_error());
+
+    // a missing flag explains more than the edges stopped on the way
+    let mut static_req = req.clone();
+    static_req.forbidden.clear();
+    static_req.require("static");
+    let err = bggr.solve(static_req).unwrap_err();
+    assert!(err.to_string().starts_with("no solution with flag \"static\""), "{}", err);
+
+    // async bags can not retry
+    let mut async_req = req.clone();
+    async_req.target = BagInfo::from_quote(parse_quote!(AsyncBag<str>)).unwrap();
+    let err = bggr.solve(async_req).unwrap_err();
+    assert!(err.to_string().contains("retry is not supported"), "{}", err);
+
+    req.arg("retry", "often");
+    assert!(bggr.solve(req).is_err());
+}
+
+#[test]
+pub fn solve_reloading_str() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.require("reload");
+
+    let sol = bggr.solve(req.clone()).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! {
+            ::bag::ops::reloading_runtime_file::<String>(
+                ::bag::alias::RuntimePath::new("tests/hello.txt", "tests/hello.txt"))
+        },
+    );
+    assert_eq!(
+        sol.bag_expr.returns,
+        parse_quote!(::bag::ops::ReloadingRuntimeFile<String>),
+    );
+
+    // retrying is not supported
+    let mut retrying = req.clone();
+    retrying.arg("retry", "3");
+    let err = bggr.solve(retrying).unwrap_err();
+    let graph = err.downcast::<NoSolution>().unwrap().graph;
+    assert!(graph.nodes.iter().any(|n| n.stop.as_ref()
+        .map(|s| s.contains("not supported by reloading bags"))
+        .unwrap_or(false)));
+
+    // never chosen for static data
+    req.require("static");
+    assert!(bggr.solve(req).is_err());
+
+    // nor unless asked for
+    let mut req = BagRequest::new(
+        Uri::from_str("tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.forbid("static");
+    let sol = bggr.solve(req).unwrap();
+    assert!(!sol.bag_expr.expr.to_string().contains("reloading"));
+}
+
+#[test]
+pub fn solve_format_loader() {
+    let config = Config::parse(r#"
+        [format."::image::RgbaImage"]
+        loader = "::bag_image::LoadImageBuffer"
+    "#).unwrap();
+    let bggr = Bagger::with_config(config).unwrap();
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/tiny.png").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<::image::RgbaImage>)).unwrap());
+    req.require("include");
+
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! {
+            ::bag::bags::TryLazyMap::<
+                (&'static [u8],),
+                ::image::RgbaImage,
+                fn((&'static [u8],)) -> Result<::image::RgbaImage, ::bag::fail::Error>
+            >::new(
+                ({ include_bytes!("./tests/tiny.png") },),
+                |(bytes,)| <::bag_image::LoadImageBuffer as ::bag::ops::Loader<::image::RgbaImage>>::load(bytes)
+            )
+        },
+    );
+}
+
+#[test]
+pub fn invalid_format() {
+    let config = Config::parse(r#"
+        [format."::image::RgbaImage"]
+        loader = "not a path"
+    "#).unwrap();
+    assert!(Bagger::with_config(config).is_err());
+}
+
+#[test]
+pub fn explain_no_solution() {
+    let bggr = Bagger::new();
+    let ty = BagInfo::simple(parse_quote!(str), None);
+    let mut req = BagRequest::new(Uri::from_str("./tests/tiny.png").unwrap(), ty);
+    req.require("include");
+
+    let err = bggr.solve(req).unwrap_err();
+    let report = err.downcast_ref::<NoSolution>().unwrap();
+    assert_eq!(report.graph.nodes[0].name, "Request(./tests/tiny.png)");
+    assert!(report.graph.nodes.iter()
+        .any(|n| n.stop.as_ref().map(|s| s == "file content is not text").unwrap_or(false)));
+    assert!(err.to_string().contains("stopped at"));
+}
+
+#[test]
+pub fn export_graph() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    req.require("include");
+
+    let (sol, graph) = bggr.solve_with_graph(req);
+    assert!(sol.is_ok());
+    let end = graph.end.unwrap();
+    assert_eq!(graph.path(end).len(), 3);
+    assert_eq!(graph.nodes[end].satisfies, vec!["include"]);
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph search {"));
+    assert!(dot.contains("n0 [label=\"Request(./tests/hello.txt)\", style=bold];"));
+    assert!(dot.contains("n0 -> n1 [label=\"0\", style=bold];"));
+
+    let json = graph.to_json().unwrap();
+    assert!(json.contains("\"name\": \"LocalPath(./tests/hello.txt)\""));
+    assert!(json.contains(&format!("\"end\": {}", end)));
+}
+
+struct Ping(bool);
+impl Node for Ping {
+    type Target = ();
+
+    fn key(&self) -> Option<NodeKey> {
+        node_key(self.0)
+    }
+}
+
+struct Pong(bool);
+impl Node for Pong {
+    type Target = ();
+}
+
+#[test]
+pub fn dedup_cycles() {
+    let mut bggr = Bagger::new();
+    bggr.transform(|mut n: NodeInput<LocalPath>| n.edges.add(Ping(false), EdgeBuilder::new()));
+    bggr.transform(|mut n: NodeInput<Ping>| {
+        let flip = !n.node.0;
+        n.edges.add(Ping(flip), EdgeBuilder::new())
+    });
+
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<u32>)).unwrap());
+    let (sol, graph) = bggr.solve_with_graph(req);
+    let err = sol.unwrap_err();
+    assert!(err.to_string().starts_with("no solution"));
+    assert!(graph.nodes.iter().any(|n| n.name == "Ping" && n.duplicate.is_some()));
+}
+
+/// A key whose hash is the same for every value.
+#[derive(PartialEq, Eq)]
+struct Collide(u8);
+impl ::std::hash::Hash for Collide {
+    fn hash<H: ::std::hash::Hasher>(&self, _: &mut H) {}
+}
+
+struct Tag(u8);
+impl Node for Tag {
+    type Target = ();
+
+    fn key(&self) -> Option<NodeKey> {
+        node_key(Collide(self.0))
+    }
+}
+
+#[test]
+pub fn dedup_hash_collision() {
+    let mut bggr = Bagger::new();
+    bggr.transform(|mut n: NodeInput<LocalPath>| {
+        n.edges.add(Tag(0), EdgeBuilder::new());
+        n.edges.add(Tag(1), EdgeBuilder::new());
+    });
+
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<u32>)).unwrap());
+    let (sol, graph) = bggr.solve_with_graph(req);
+    assert!(sol.is_err());
+    // the keys hash the same, but are not equivalent
+    let tags: Vec<_> = graph.nodes.iter().filter(|n| n.name == "Tag").collect();
+    assert_eq!(tags.len(), 2);
+    assert!(tags.iter().all(|n| n.duplicate.is_none()));
+}
+
+#[test]
+pub fn expansion_budget() {
+    let mut bggr = Bagger::with_config(Config::parse(r#"
+        [search]
+        max_expansions = 100
+    "#).unwrap()).unwrap();
+    bggr.transform(|mut n: NodeInput<LocalPath>| n.edges.add(Pong(false), EdgeBuilder::new()));
+    bggr.transform(|mut n: NodeInput<Pong>| {
+        let flip = !n.node.0;
+        n.edges.add(Pong(flip), EdgeBuilder::new())
+    });
+
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<u32>)).unwrap());
+    let err = bggr.solve(req).unwrap_err();
+    assert!(err.to_string().starts_with("search gave up after expanding 100 nodes"));
+}
+
+#[test]
+pub fn dead_nodes_outside_budget() {
+    let mut bggr = Bagger::with_config(Config::parse(r#"
+        [search]
+        max_expansions = 100
+    "#).unwrap()).unwrap();
+    bggr.transform(|mut n: NodeInput<LocalPath>| for _ in 0..200 {
+        let mut edge = EdgeBuilder::new();
+        edge.stop(format_err!("pong is stopped"));
+        n.edges.add(Pong(false), edge)
+    });
+    bggr.transform(|mut n: NodeInput<Pong>| n.edges.add(Pong(!n.node.0), EdgeBuilder::new()));
+
+    let req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<u32>)).unwrap());
+    let err = bggr.solve(req).unwrap_err();
+    // stopped nodes are reported, but never expanded
+    assert!(!err.to_string().starts_with("search gave up"), "{}", err);
+    let graph = &err.downcast_ref::<NoSolution>().unwrap().graph;
+    let stopped = graph.nodes.iter()
+        .filter(|n| n.stop.as_ref().map(|s| s == "pong is stopped").unwrap_or(false))
+        .count();
+    assert_eq!(stopped, 200);
+}
+
+#[test]
+pub fn solve_past_unmet_flags() {
+    // a plugin that is cheaper than anything builtin, but not static
+    let mut bggr = Bagger::new();
+    bggr.transform(|mut n: NodeInput<LocalPath>| {
+        let mut edge = EdgeBuilder::new();
+        edge.value(|_| Ok(BagExpr {
+            expr: quote! { not_static() },
+            returns: parse_quote!(NotStatic),
+        }));
+        let info = BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap();
+        n.edges.add(Producer(info), edge);
+    });
+
+    let request = || BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    let sol = bggr.solve(request()).unwrap();
+    assert_eq!(sol.bag_expr.expr, quote! { not_static() });
+
+    let mut req = request();
+    req.require("static");
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(
+        sol.bag_expr.expr,
+        quote! { ::bag::bags::Static::<&'static str>({ include_str!("./tests/hello.txt") }) },
+    );
+}
+
+#[test]
+pub fn unmet_flags() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.require("static");
+    let err = bggr.solve(req).unwrap_err();
+    assert!(err.to_string().starts_with("no solution with flag \"static\""));
+    assert!(err.to_string().contains("missing flag \"static\""));
+
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    req.require("sparkly");
+    let err = bggr.solve(req).unwrap_err();
+    assert!(err.to_string().starts_with("no solution with flag \"sparkly\""));
+    // no builtin edge satisfies the flag, so nothing past the request is explored
+    let graph = &err.downcast_ref::<NoSolution>().unwrap().graph;
+    assert_eq!(graph.nodes.len(), 1);
+    assert!(graph.nodes[0].stop.as_ref().unwrap().contains("nothing after this node satisfies"));
+}
+
+#[test]
+pub fn prune_unreachable_flags() {
+    let bggr = Bagger::new();
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(TryBag<str>)).unwrap());
+    req.require("static");
+    let (sol, graph) = bggr.solve_with_graph(req);
+    assert!(sol.is_err());
+    // producers loading at run time can never become static
+    let pruned = graph.nodes.iter()
+        .filter(|n| n.stop.as_ref().map(|s| s.contains("nothing after this node")).unwrap_or(false))
+        .count();
+    assert!(pruned > 0, "{:#?}", graph);
+}
+
+#[test]
+pub fn undeclared_flags() {
+    // a plugin that satisfies a flag without declaring it, or its edges
+    let mut bggr = Bagger::new();
+    bggr.transform(|mut n: NodeInput<LocalPath>| {
+        let mut edge = EdgeBuilder::new();
+        edge.satisfies("sparkly");
+        edge.value(|_| Ok(BagExpr {
+            expr: quote! { sparkly() },
+            returns: parse_quote!(Sparkly),
+        }));
+        let info = BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap();
+        n.edges.add(Producer(info), edge);
+    });
+
+    let mut req = BagRequest::new(
+        Uri::from_str("./tests/hello.txt").unwrap(),
+        BagInfo::from_quote(parse_quote!(Bag<str>)).unwrap());
+    req.require("sparkly");
+    assert!(!bggr.check_request(&req)[0].is_error());
+    let sol = bggr.solve(req).unwrap();
+    assert_eq!(sol.bag_expr.expr, quote! { sparkly() });
+}
@@ -0,0 +1,9 @@
+use std::env;
+
+fn main() {
+    // cargo only tells build scripts which target and profile are being
+    // built, so pass them on to `bag!` for the `[target]` and `[profile]`
+    // sections of Bag.toml
+    println!("cargo:rustc-env=TARGET={}", env::var("TARGET").unwrap());
+    println!("cargo:rustc-env=PROFILE={}", env::var("PROFILE").unwrap());
+}